@@ -1,7 +1,8 @@
 use std::{path::PathBuf, pin::pin};
 
 use bytes::Bytes;
-use flowly::{Service, ServiceExt, flow};
+use flowly::{EncodedFrame, Frame, Service, ServiceExt, flow};
+use flowly_mpegts::Mpeg2TsDemux;
 use futures::TryStreamExt;
 use tokio::io::AsyncReadExt;
 
@@ -35,29 +36,28 @@ impl<E: std::error::Error + Send + Sync + 'static> Service<Result<PathBuf, E>> f
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    // let reader = flow().flow(FileReader1).flow(Mpeg2TsDemux::new());
-
-    // let stream = reader.handle(futures::stream::once(async move {
-    //     PathBuf::from_str("/home/andrey/demo/h264/oxford_shoppers.ts")
-    //     // PathBuf::from_str("/home/andrey/demo/h265/street.ts")
-    // }));
-
-    // let mut stream = pin!(stream);
-
-    // while let Some(frame) = stream.try_next().await? {
-    //     println!();
-
-    //     for unit in frame.units() {
-    //         println!(
-    //             "{:0.2}\t{}\t {} {} {}",
-    //             (frame.pts() as f64) / 1_000_000.0,
-    //             frame.has_params(),
-    //             frame.params().count(),
-    //             frame.is_keyframe(),
-    //             unit.len()
-    //         );
-    //     }
-    // }
+    let path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("usage: demux <file.ts>"))?;
+
+    let reader = flow().flow(FileReader1).flow(Mpeg2TsDemux::<()>::default());
+    let stream = reader.handle(futures::stream::once(async move {
+        Ok::<_, std::io::Error>(path)
+    }));
+
+    let mut stream = pin!(stream);
+
+    while let Some(frame) = stream.try_next().await? {
+        println!(
+            "{:0.2}\t{}\t{} {} {}",
+            (frame.pts() as f64) / 1_000_000.0,
+            frame.has_params(),
+            frame.params().count(),
+            frame.is_keyframe(),
+            frame.payload().len()
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,64 @@
+#![no_main]
+
+use bytes::BytesMut;
+use flowly_mpegts::{
+    KeyframeDetection, Mpeg2TsDemux, Mpeg2TsDemuxConfig, PacketFormat, ProgramSelection,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Picks the demuxer config from the first input byte.
+fn config(flags: u8) -> Mpeg2TsDemuxConfig {
+    Mpeg2TsDemuxConfig {
+        programs: if flags & 0x01 != 0 {
+            ProgramSelection::Only(vec![1])
+        } else {
+            ProgramSelection::All
+        },
+        keyframes: match (flags >> 1) & 0x03 {
+            0 => KeyframeDetection::RandomAccess,
+            1 => KeyframeDetection::Nal,
+            _ => KeyframeDetection::Both,
+        },
+        split_access_units: flags & 0x08 != 0,
+        events: flags & 0x10 != 0,
+        format: match (flags >> 5) & 0x03 {
+            0 => None,
+            1 => Some(PacketFormat::Ts),
+            2 => Some(PacketFormat::M2ts),
+            _ => Some(PacketFormat::Rs),
+        },
+        sync_packets: if flags & 0x80 != 0 { 1 } else { 3 },
+    }
+}
+
+/// Parses events until more input is needed.
+fn drain(demux: &mut Mpeg2TsDemux, buf: &mut BytesMut) {
+    // Every call consumes at least one packet, errors included, so this terminates.
+    loop {
+        match demux.parse_event(buf) {
+            Ok(Some(_)) | Err(_) => {}
+            Ok(None) => break,
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+
+    let mut demux = Mpeg2TsDemux::<()>::with_config((), 0, config(flags));
+
+    // feed the input in two chunks to cover packets split across calls
+    let (head, tail) = data.split_at(data.len() / 2);
+    let mut buf = BytesMut::from(head);
+    drain(&mut demux, &mut buf);
+
+    buf.extend_from_slice(tail);
+    drain(&mut demux, &mut buf);
+
+    demux.end_of_input();
+    drain(&mut demux, &mut buf);
+
+    while demux.flush_event().is_some() {}
+});
@@ -1,133 +1,1382 @@
-use bytes::{BufMut, BytesMut};
-use flowly::Fourcc;
-use std::collections::HashMap;
-
-use bytes::Buf;
-use mpeg2ts::{
-    es::StreamType,
-    ts::{ContinuityCounter, Pid, ReadTsPacket, TsPacket, TsPacketReader, TsPayload},
+mod access_unit;
+mod assembler;
+mod audio;
+mod continuity;
+mod event;
+mod nal;
+mod sync;
+
+pub use event::{Mpeg2TsDemuxEvent, ProgramInfo, StreamInfo};
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    pin::pin,
+    sync::Arc,
 };
 
-use crate::error::Error;
+use bytes::{Buf, Bytes, BytesMut};
+use flowly::{Fourcc, FrameSource, Service};
+use futures::StreamExt;
 
-pub struct Mpeg2TsDemux {
-    ignore: bool,
-    mappings: Option<HashMap<Pid, PidKind>>,
-    base_ts: u64,
+use crate::{
+    demux::{
+        access_unit::{AccessUnit, AccessUnitSplitter, PesInfo, TimestampInterpolator},
+        assembler::{PesAssembler, PesPacket},
+        audio::{AudioCodec, AudioSplitter},
+        continuity::{Continuity, ContinuityChecker},
+        nal::{NalCodec, ParamSets},
+        sync::PacketSync,
+    },
+    error::Error,
+    format::PacketFormat,
+    frame::{Mpeg2TsAudioFrame, Mpeg2TsDemuxFrame, Mpeg2TsFrame, Mpeg2TsSource},
+    mpegts::{
+        io::{Io, Mpeg2tsParser},
+        pid::Pid,
+        stream_type::StreamType,
+        timestamp::{Clock, PCR, PtsDts, Timestamp, Unwrapper},
+        ts::{EsInfo, Pat, Pmt, TsPacket, TsPayload, VersionedTable},
+    },
+};
+
+/// DVB descriptor tags that mark private PES streams as Dolby audio.
+const AC3_DESCRIPTOR_TAG: u8 = 0x6A;
+const ENHANCED_AC3_DESCRIPTOR_TAG: u8 = 0x7A;
+
+/// Bits of the M2TS TP_extra_header holding the arrival time stamp.
+const ARRIVAL_TIME_MASK: u32 = 0x3FFF_FFFF;
+
+/// Largest PCR step in 27 MHz units not reported as a discontinuity.
+const PCR_MAX_DELTA: u64 = 27_000_000;
+
+#[derive(Debug, Clone, Copy)]
+enum EsCodec {
+    Video(NalCodec),
+    Audio(AudioCodec),
+}
+
+impl EsCodec {
+    fn from_es_info(es: &EsInfo) -> Option<Self> {
+        let has_descriptor = |tag| es.descriptors.iter().any(|d| d.tag == tag);
+
+        Some(match es.stream_type {
+            StreamType::H264 => EsCodec::Video(NalCodec::H264),
+            StreamType::H265 => EsCodec::Video(NalCodec::H265),
+            StreamType::AdtsAac => EsCodec::Audio(AudioCodec::Aac),
+            StreamType::Mpeg1Audio | StreamType::Mpeg2HalvedSampleRateAudio => {
+                EsCodec::Audio(AudioCodec::Mpeg)
+            }
 
-    #[allow(dead_code)]
-    cc: ContinuityCounter,
-    fragm_idx: u64,
-    frame_idx: u64,
-    codec: Fourcc,
-    is_keyframe: bool,
-    is_params_updated: bool,
-    tags: BytesMut,
+            StreamType::DolbyDigitalUpToSixChannelAudio => EsCodec::Audio(AudioCodec::Ac3),
+            StreamType::DolbyDigitalPlusUpTo16ChannelAudio
+            | StreamType::DolbyDigitalPlusUpTo16ChannelAudioForAtsc => {
+                EsCodec::Audio(AudioCodec::Eac3)
+            }
+
+            StreamType::Mpeg2PacketizedData if has_descriptor(AC3_DESCRIPTOR_TAG) => {
+                EsCodec::Audio(AudioCodec::Ac3)
+            }
+
+            StreamType::Mpeg2PacketizedData if has_descriptor(ENHANCED_AC3_DESCRIPTOR_TAG) => {
+                EsCodec::Audio(AudioCodec::Eac3)
+            }
+
+            _ => return None,
+        })
+    }
+
+    fn fourcc(self) -> Fourcc {
+        match self {
+            EsCodec::Video(codec) => codec.fourcc(),
+            EsCodec::Audio(codec) => codec.fourcc(),
+        }
+    }
+}
+
+enum StreamKind {
+    Video {
+        codec: NalCodec,
+        params: ParamSets,
+        splitter: Option<Box<AccessUnitSplitter>>,
+
+        /// Timestamps of PES packets without a PTS, unless split into access units.
+        timestamps: TimestampInterpolator,
+    },
+    Audio(AudioSplitter),
+}
+
+struct ElementaryStream<S: FrameSource> {
+    kind: StreamKind,
+    source: Arc<Mpeg2TsSource<S>>,
+
+    /// Unwraps the PES timestamps of the stream.
+    timeline: Unwrapper<PtsDts>,
+
+    /// Last PTS and DTS in microseconds.
     pts: u64,
-    body: BytesMut,
+    dts: u64,
+}
+
+/// Programs of a multi-program transport stream the demuxer emits frames for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ProgramSelection {
+    #[default]
+    All,
+
+    /// Only the programs with these `program_number` values.
+    Only(Vec<u16>),
+}
+
+impl ProgramSelection {
+    pub fn contains(&self, program_num: u16) -> bool {
+        match self {
+            ProgramSelection::All => true,
+            ProgramSelection::Only(programs) => programs.contains(&program_num),
+        }
+    }
+}
+
+/// How the demuxer decides which video frames are keyframes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyframeDetection {
+    /// Trust the `random_access_indicator` of the adaptation field.
+    RandomAccess,
+
+    /// Look for IDR, recovery point and IRAP NAL units.
+    Nal,
+
+    /// Either of the above.
+    #[default]
+    Both,
 }
 
-impl Mpeg2TsDemux {
-    pub fn new(base_ts: u64, fragm_idx: u64) -> Self {
+#[derive(Debug, Clone)]
+pub struct Mpeg2TsDemuxConfig {
+    pub programs: ProgramSelection,
+    pub keyframes: KeyframeDetection,
+
+    /// Split video PES packets into access units, so that every frame holds
+    /// exactly one picture however the encoder packed them.
+    pub split_access_units: bool,
+
+    /// Report stream-structure events besides frames, see [`Mpeg2TsDemux::parse_event`].
+    pub events: bool,
+
+    /// Framing of the input packets, detected from the data if `None`.
+    pub format: Option<PacketFormat>,
+
+    /// Number of consecutive packets that must start with the sync byte before
+    /// the demuxer locks onto the packet boundaries.
+    pub sync_packets: usize,
+}
+
+impl Default for Mpeg2TsDemuxConfig {
+    fn default() -> Self {
         Self {
-            ignore: true,
-            mappings: None,
-            base_ts,
-            fragm_idx,
-            frame_idx: 0,
-            is_params_updated: false,
-            tags: BytesMut::with_capacity(64 * 1024 * 1024),
-            is_keyframe: false,
-            codec: Fourcc::default(),
-            pts: 0,
-            cc: ContinuityCounter::new(),
-            body: BytesMut::with_capacity(64 * 1024 * 1024),
+            programs: ProgramSelection::All,
+            keyframes: KeyframeDetection::Both,
+            split_access_units: false,
+            events: false,
+            format: None,
+            sync_packets: 3,
         }
     }
 }
 
-impl Mpeg2TsDemux {
-    fn parse(&mut self, src: &mut BytesMut) -> Result<Option<Mpeg2TsFrame>, Error> {
-        let mut reader = if let Some(map) = self.mappings.take() {
-            TsPacketReader::with_mappings(src.reader(), map)
-        } else {
-            TsPacketReader::new(src.reader())
+pub struct Mpeg2TsDemux<S: FrameSource = ()> {
+    config: Mpeg2TsDemuxConfig,
+    parser: Mpeg2tsParser,
+    sync: PacketSync,
+    skipped_bytes: u64,
+    assembler: PesAssembler,
+    continuity: ContinuityChecker,
+    pending: VecDeque<PesPacket>,
+    events: VecDeque<Mpeg2TsDemuxEvent<S>>,
+    streams: HashMap<Pid, ElementaryStream<S>>,
+    pcrs: HashMap<Pid, Unwrapper<Clock<PCR>>>,
+    unknown_pids: HashSet<u16>,
+    base_ts: u64,
+    source: S,
+}
+
+impl<S: FrameSource + Clone> Mpeg2TsDemux<S> {
+    /// Makes a demuxer whose frame timestamps are offset by `base_ts` microseconds.
+    pub fn new(source: S, base_ts: u64) -> Self {
+        Self::with_config(source, base_ts, Mpeg2TsDemuxConfig::default())
+    }
+
+    pub fn with_config(source: S, base_ts: u64, config: Mpeg2TsDemuxConfig) -> Self {
+        Self {
+            sync: PacketSync::new(config.sync_packets, config.format),
+            skipped_bytes: 0,
+            config,
+            parser: Mpeg2tsParser::new(),
+            assembler: PesAssembler::new(),
+            continuity: ContinuityChecker::new(),
+            pending: VecDeque::new(),
+            events: VecDeque::new(),
+            streams: HashMap::new(),
+            pcrs: HashMap::new(),
+            unknown_pids: HashSet::new(),
+            base_ts,
+            source,
+        }
+    }
+
+    /// Parses complete TS packets from `src` until a frame is ready.
+    ///
+    /// Video PES packets are returned as one frame, audio PES packets are split
+    /// into codec frames. A PES packet is processed as soon as it is complete:
+    /// once `PES_packet_length` bytes arrived, once the data ends with an access
+    /// unit delimiter or end-of-sequence NAL unit, or else when the next PES
+    /// packet starts on the same PID.
+    ///
+    /// The input does not have to start on a packet boundary: bytes in front of
+    /// the first packet and garbage between packets are skipped.
+    ///
+    /// Bytes of a trailing partial packet are left in `src`, as is the last
+    /// packet until the sync byte of the next one arrived or
+    /// [`end_of_input`](Self::end_of_input) was called.
+    pub fn parse(&mut self, src: &mut BytesMut) -> Result<Option<Mpeg2TsDemuxFrame<S>>, Error> {
+        while let Some(event) = self.parse_event(src)? {
+            if let Mpeg2TsDemuxEvent::Frame(frame) = event {
+                return Ok(Some(frame));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Like [`parse`](Self::parse), but returns the next event.
+    ///
+    /// Events other than frames are only reported if `Mpeg2TsDemuxConfig::events` is set.
+    pub fn parse_event(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<Mpeg2TsDemuxEvent<S>>, Error> {
+        loop {
+            if let Some(event) = self.next_event() {
+                return Ok(Some(event));
+            }
+
+            let alignment = self.sync.align(src);
+            if alignment.skipped > 0 {
+                self.skipped_bytes += alignment.skipped as u64;
+                self.report(|| Mpeg2TsDemuxEvent::SyncLost {
+                    skipped: alignment.skipped,
+                });
+            }
+
+            if !alignment.ready {
+                // return a just reported event before waiting for more data
+                return Ok(self.next_event());
+            }
+
+            let Some(format) = self.sync.format() else {
+                return Ok(None);
+            };
+
+            let mut packet = src.split_to(format.size());
+            let arrival_time = match format {
+                PacketFormat::Ts => None,
+                PacketFormat::M2ts => Some(packet.get_u32() & ARRIVAL_TIME_MASK),
+                PacketFormat::Rs => {
+                    // drop the parity bytes
+                    packet.truncate(TsPacket::SIZE);
+                    None
+                }
+            };
+
+            self.parse_packet(&mut packet, arrival_time)?;
+        }
+    }
+
+    /// Marks the end of the input.
+    ///
+    /// The packets left at the end of `src` are then parsed even if there are
+    /// fewer of them than `Mpeg2TsDemuxConfig::sync_packets`. Call
+    /// [`parse`](Self::parse) once more to drain them before [`flush`](Self::flush).
+    pub fn end_of_input(&mut self) {
+        self.sync.end_of_input();
+    }
+
+    /// Returns the frames still held in the per-PID PES buffers.
+    ///
+    /// Call it at the end of the input, e.g. after the last packet of a segment,
+    /// and repeat until it returns `None`.
+    pub fn flush(&mut self) -> Option<Mpeg2TsDemuxFrame<S>> {
+        while let Some(event) = self.flush_event() {
+            if let Mpeg2TsDemuxEvent::Frame(frame) = event {
+                return Some(frame);
+            }
+        }
+
+        None
+    }
+
+    /// Like [`flush`](Self::flush), but returns the next event.
+    ///
+    /// Access units held by the splitters of video streams are returned once
+    /// every PES buffer is drained.
+    pub fn flush_event(&mut self) -> Option<Mpeg2TsDemuxEvent<S>> {
+        self.assembler.flush(&mut self.pending);
+        if let Some(event) = self.next_event() {
+            return Some(event);
+        }
+
+        let mut pids: Vec<_> = self.streams.keys().copied().collect();
+        pids.sort_unstable();
+
+        for pid in pids {
+            let mut units = Vec::new();
+            if let Some(stream) = self.streams.get_mut(&pid)
+                && let StreamKind::Video {
+                    splitter: Some(splitter),
+                    ..
+                } = &mut stream.kind
+            {
+                splitter.flush(&mut units);
+            }
+
+            self.make_video_frames(pid, units);
+        }
+
+        self.next_event()
+    }
+
+    /// Turns the demuxer into a `Service` that yields every event instead of frames only.
+    pub fn into_events(mut self) -> Mpeg2TsDemuxEvents<S> {
+        self.config.events = true;
+        Mpeg2TsDemuxEvents(self)
+    }
+
+    /// Returns the framing of the input while the demuxer is in sync.
+    pub fn format(&self) -> Option<PacketFormat> {
+        self.sync.format()
+    }
+
+    /// Returns the last PCR received on `pid`, unwrapped into a monotonic
+    /// timeline in 27 MHz units.
+    pub fn pcr(&self, pid: u16) -> Option<u64> {
+        self.pcrs.get(&Pid(pid))?.last()
+    }
+
+    /// Returns the number of bytes skipped so far to find packet boundaries.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Returns the numbers of the selected programs announced so far.
+    pub fn programs(&self) -> Vec<u16> {
+        let mut programs: Vec<_> = self
+            .streams
+            .values()
+            .map(|stream| stream.source.program_num)
+            .collect();
+
+        programs.sort_unstable();
+        programs.dedup();
+        programs
+    }
+
+    /// Returns the sources of the elementary streams of a program, in PID order.
+    pub fn program_streams(&self, program_num: u16) -> Vec<Arc<Mpeg2TsSource<S>>> {
+        let mut streams: Vec<_> = self
+            .streams
+            .values()
+            .filter(|stream| stream.source.program_num == program_num)
+            .map(|stream| stream.source.clone())
+            .collect();
+
+        streams.sort_by_key(|source| source.pid);
+        streams
+    }
+
+    fn next_event(&mut self) -> Option<Mpeg2TsDemuxEvent<S>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+
+            let packet = self.pending.pop_front()?;
+            self.make_frames(packet);
+        }
+    }
+
+    /// Queues a non-frame event if events are enabled.
+    fn report(&mut self, event: impl FnOnce() -> Mpeg2TsDemuxEvent<S>) {
+        if self.config.events {
+            self.events.push_back(event());
+        }
+    }
+
+    fn parse_packet(
+        &mut self,
+        packet: &mut BytesMut,
+        arrival_time: Option<u32>,
+    ) -> Result<(), Error> {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = Pid(u16::from_be_bytes([packet[1] & 0x1F, packet[2]]));
+
+        let pkt: TsPacket = match self.parser.parse(packet) {
+            Ok(pkt) => pkt,
+            Err(Error::UnknownPid(pid)) => {
+                if self.unknown_pids.insert(pid) {
+                    self.report(|| Mpeg2TsDemuxEvent::UnknownPid(pid));
+                }
+
+                return Ok(());
+            }
+            // streams outside the selected programs are not demuxed and cannot fail
+            Err(_) if self.parser.is_pes_pid(pid) && !self.streams.contains_key(&pid) => {
+                return Ok(());
+            }
+
+            Err(err) => {
+                // the pending PES packet ends where the unreadable one starts,
+                // its continuations must not be appended to it
+                if unit_start {
+                    self.assembler.close(pid, &mut self.pending);
+                }
+
+                return Err(err);
+            }
         };
 
-        while reader.stream().get_ref().remaining() >= TsPacket::SIZE {
-            let pkt = reader.read_ts_packet()?.unwrap();
-            let is_keyframe = pkt
-                .adaptation_field
-                .as_ref()
-                .map(|x| x.random_access_indicator)
-                .unwrap_or(false);
-
-            if let Some(p) = pkt.payload {
-                match p {
-                    TsPayload::Pat(_pat) => {}
-                    TsPayload::Pmt(pmt) => {
-                        for es in pmt.es_info {
-                            match es.stream_type {
-                                StreamType::H264 => self.codec = Fourcc::VIDEO_AVC,
-                                StreamType::H265 => self.codec = Fourcc::VIDEO_HEVC,
-                                _ => (),
-                            }
+        while let Some((pid, table)) = self.parser.pop_table() {
+            match table.header.table_id {
+                Pat::TABLE_ID if pid.as_u16() == Pid::PAT => {
+                    let mut programs = Vec::new();
+                    for section in &table.sections {
+                        for pa in self.parser.decode_pat(section)?.table {
+                            programs.push(ProgramInfo {
+                                program_num: pa.program_num,
+                                pmt_pid: pa.program_map_pid.as_u16(),
+                            });
                         }
                     }
 
-                    TsPayload::Pes(pes) => {
-                        let frame = (!self.ignore).then(|| {
-                            Frame::new(
-                                self.codec,
-                                self.fragm_idx,
-                                self.frame_idx,
-                                self.pts,
-                                self.pts + self.base_ts,
-                                self.is_keyframe,
-                                self.is_params_updated,
-                                self.tags.clone().into(),
-                                true,
-                                (0, 0),
-                                self.source.clone(),
-                                None::<Vec<Vec<u8>>>,
-                                false,
-                                self.body.to_vec(),
-                            )
+                    self.report(|| Mpeg2TsDemuxEvent::PatUpdated {
+                        transport_stream_id: table.table_id_extension,
+                        version: table.version_number.as_u8(),
+                        programs,
+                    });
+                }
+
+                Pmt::TABLE_ID => self.update_program(&table)?,
+
+                table_id => self.report(|| Mpeg2TsDemuxEvent::Section {
+                    pid: pid.as_u16(),
+                    table_id,
+                    table_id_extension: table.table_id_extension,
+                    version: table.version_number.as_u8(),
+                    data: table
+                        .sections
+                        .iter()
+                        .map(|x| x.table_data.clone())
+                        .collect(),
+                }),
+            }
+        }
+
+        while let Some((pid, section)) = self.parser.pop_private_section() {
+            self.report(|| Mpeg2TsDemuxEvent::Section {
+                pid: pid.as_u16(),
+                table_id: section.header.table_id,
+                table_id_extension: 0,
+                version: 0,
+                data: vec![section.private_data],
+            });
+        }
+
+        let pid = pkt.header.pid;
+        let discontinuity = pkt
+            .adaptation_field
+            .as_ref()
+            .is_some_and(|x| x.discontinuity_indicator);
+
+        match self.continuity.check(&pkt.header, discontinuity) {
+            Continuity::Ok => {}
+            Continuity::Duplicate => return Ok(()),
+            Continuity::Gap { expected } => {
+                self.assembler.mark_corrupt(pid);
+                self.report(|| Mpeg2TsDemuxEvent::ContinuityError {
+                    pid: pid.as_u16(),
+                    expected: expected.as_u8(),
+                    actual: pkt.header.continuity_counter.as_u8(),
+                });
+            }
+        }
+
+        if let Some(af) = &pkt.adaptation_field {
+            if let Some(pcr) = af.pcr {
+                self.check_pcr(pid, pcr, af.discontinuity_indicator);
+            }
+
+            if let Some(countdown) = af.splice_countdown {
+                self.report(|| Mpeg2TsDemuxEvent::SpliceCountdown {
+                    pid: pid.as_u16(),
+                    countdown,
+                });
+            }
+        }
+
+        let random_access = pkt
+            .adaptation_field
+            .as_ref()
+            .map(|x| x.random_access_indicator)
+            .unwrap_or(false);
+
+        if !self.streams.contains_key(&pid) {
+            return Ok(());
+        }
+
+        match pkt.payload {
+            Some(TsPayload::Pes(pes)) => self.assembler.start(
+                pid,
+                pes.header,
+                random_access,
+                arrival_time,
+                &pes.data,
+                &mut self.pending,
+            ),
+
+            Some(TsPayload::Raw(raw)) => self.assembler.append(pid, &raw, &mut self.pending),
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn check_pcr(&mut self, pid: Pid, pcr: Timestamp<Clock<PCR>>, discontinuity: bool) {
+        let unwrapper = self.pcrs.entry(pid).or_default();
+        let previous = unwrapper.last();
+
+        let jumped = unwrapper.last_timestamp().is_some_and(|last| {
+            pcr.wrapping_cmp(&last).is_lt() || pcr.wrapping_sub(&last) > PCR_MAX_DELTA as i64
+        });
+
+        let current = unwrapper.extend(pcr);
+
+        if discontinuity || jumped {
+            self.report(|| Mpeg2TsDemuxEvent::PcrDiscontinuity {
+                pid: pid.as_u16(),
+                previous,
+                current,
+            });
+        }
+    }
+
+    fn update_program(&mut self, table: &VersionedTable) -> Result<(), Error> {
+        for section in &table.sections {
+            let pmt = self.parser.decode_pmt(section)?;
+
+            self.report(|| Mpeg2TsDemuxEvent::PmtUpdated {
+                program_num: pmt.program_num,
+                version: pmt.version_number.as_u8(),
+                pcr_pid: pmt.pcr_pid.map(|pid| pid.as_u16()),
+                streams: pmt
+                    .es_info
+                    .iter()
+                    .map(|es| StreamInfo {
+                        pid: es.elementary_pid.as_u16(),
+                        stream_type: es.stream_type.into(),
+                        codec: EsCodec::from_es_info(es).map(EsCodec::fourcc),
+                    })
+                    .collect(),
+            });
+
+            if !self.config.programs.contains(pmt.program_num) {
+                continue;
+            }
+
+            // drop the streams the updated PMT no longer lists
+            self.streams.retain(|pid, stream| {
+                stream.source.program_num != pmt.program_num
+                    || pmt.es_info.iter().any(|es| es.elementary_pid == *pid)
+            });
+
+            for es in &pmt.es_info {
+                self.update_stream(pmt.program_num, es);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_stream(&mut self, program_num: u16, es: &EsInfo) {
+        let Some(es_codec) = EsCodec::from_es_info(es) else {
+            return;
+        };
+
+        let codec = es_codec.fourcc();
+
+        // keep the state of streams the PMT update did not change
+        if let Some(stream) = self.streams.get(&es.elementary_pid)
+            && stream.source.codec == codec
+            && stream.source.program_num == program_num
+        {
+            return;
+        }
+
+        let kind = match es_codec {
+            EsCodec::Video(codec) => {
+                self.assembler.set_codec(es.elementary_pid, codec);
+                StreamKind::Video {
+                    codec,
+                    params: ParamSets::new(codec),
+                    splitter: self
+                        .config
+                        .split_access_units
+                        .then(|| Box::new(AccessUnitSplitter::new(codec))),
+                    timestamps: TimestampInterpolator::new(),
+                }
+            }
+
+            EsCodec::Audio(codec) => StreamKind::Audio(AudioSplitter::new(codec)),
+        };
+
+        let source = Arc::new(Mpeg2TsSource::new(
+            codec,
+            program_num,
+            es.elementary_pid.as_u16(),
+            self.source.clone(),
+        ));
+
+        self.streams.insert(
+            es.elementary_pid,
+            ElementaryStream {
+                kind,
+                source,
+                timeline: Unwrapper::new(),
+                pts: 0,
+                dts: 0,
+            },
+        );
+    }
+
+    fn make_frames(&mut self, packet: PesPacket) {
+        let Some(stream) = self.streams.get_mut(&packet.pid) else {
+            return;
+        };
+
+        let header = &packet.pes.header;
+        let pts = header.pts.map(|pts| stream.timeline.extend(pts));
+        let dts = header.dts.map(|dts| stream.timeline.extend(dts));
+
+        match &mut stream.kind {
+            StreamKind::Video {
+                splitter,
+                timestamps,
+                ..
+            } => {
+                let mut info = PesInfo {
+                    pts,
+                    dts,
+                    random_access: packet.random_access,
+                    arrival_time: packet.arrival_time,
+                    corrupt: packet.corrupt,
+                };
+
+                let mut units = Vec::new();
+                match splitter {
+                    Some(splitter) => splitter.push(info, &packet.pes.data, &mut units),
+                    None => {
+                        (info.pts, info.dts) = timestamps.next(info.pts, info.dts);
+                        units.push(AccessUnit {
+                            info,
+                            data: packet.pes.data,
                         });
+                    }
+                }
 
-                        if pes.header.stream_id.as_u8() != PES_VIDEO_STREAM_ID {
-                            self.ignore = true;
-                            continue;
-                        } else {
-                            self.ignore = false;
-                        }
+                self.make_video_frames(packet.pid, units);
+            }
+
+            StreamKind::Audio(splitter) => {
+                let mut frames = Vec::new();
+                splitter.push(pts, &packet.pes.data, &mut frames);
+
+                for frame in frames {
+                    // audio frames are decoded in presentation order
+                    stream.pts = to_micros(frame.pts);
+                    stream.dts = stream.pts;
+
+                    let frame = Mpeg2TsAudioFrame::new(
+                        (stream.pts + self.base_ts) as i64,
+                        stream.dts + self.base_ts,
+                        frame.header.sample_rate,
+                        frame.header.channel_layout,
+                        frame.header.samples,
+                        frame.header.aac_config.map(|x| Bytes::copy_from_slice(&x)),
+                        packet.arrival_time,
+                        packet.corrupt,
+                        frame.data,
+                        stream.source.clone(),
+                    );
+
+                    self.events
+                        .push_back(Mpeg2TsDemuxEvent::Frame(Mpeg2TsDemuxFrame::Audio(frame)));
+                }
+            }
+        }
+    }
+
+    fn make_video_frames(&mut self, pid: Pid, units: Vec<AccessUnit>) {
+        let Some(stream) = self.streams.get_mut(&pid) else {
+            return;
+        };
+
+        let StreamKind::Video { codec, params, .. } = &mut stream.kind else {
+            return;
+        };
+
+        for unit in units {
+            if let Some(pts) = unit.info.pts {
+                stream.pts = to_micros(pts);
+                stream.dts = unit.info.dts.map_or(stream.pts, to_micros);
+            }
+
+            let (has_params, changed) = params.update(&unit.data);
+            if changed {
+                let mut source = (*stream.source).clone();
+                source.params = params.to_vec();
+                stream.source = Arc::new(source);
+            }
+
+            let keyframe = match self.config.keyframes {
+                KeyframeDetection::RandomAccess => unit.info.random_access,
+                KeyframeDetection::Nal => codec.is_keyframe(&unit.data),
+                KeyframeDetection::Both => unit.info.random_access || codec.is_keyframe(&unit.data),
+            };
+
+            let frame = Mpeg2TsFrame::new(
+                (stream.pts + self.base_ts) as i64,
+                stream.dts + self.base_ts,
+                keyframe,
+                has_params,
+                unit.info.arrival_time,
+                unit.info.corrupt,
+                unit.data,
+                stream.source.clone(),
+            );
+
+            self.events
+                .push_back(Mpeg2TsDemuxEvent::Frame(Mpeg2TsDemuxFrame::Video(frame)));
+        }
+    }
+}
+
+/// Converts 90 kHz ticks to microseconds.
+fn to_micros(ts: u64) -> u64 {
+    (ts * 1_000_000) / Timestamp::<PtsDts>::RESOLUTION
+}
 
-                        self.pts = (pes.header.pts.unwrap().as_u64() * 1_000_000) / 90_000;
-                        self.is_keyframe = is_keyframe;
+impl<S: FrameSource + Clone + Default> Default for Mpeg2TsDemux<S> {
+    fn default() -> Self {
+        Self::new(S::default(), 0)
+    }
+}
+
+impl<S: FrameSource + Clone, E: flowly::Error> Service<Result<Bytes, E>> for Mpeg2TsDemux<S> {
+    type Out = Result<Mpeg2TsDemuxFrame<S>, Error<E>>;
 
-                        self.body.clear();
-                        self.body.put_slice(&pes.data);
+    fn handle(
+        mut self,
+        input: impl futures::Stream<Item = Result<Bytes, E>> + Send,
+    ) -> impl futures::Stream<Item = Self::Out> + Send {
+        async_stream::stream! {
+            let mut input = pin!(input);
+            let mut buffer = BytesMut::new();
 
-                        if frame.is_some() {
-                            self.frame_idx += 1;
-                            self.mappings.replace(reader.into_mappings());
-                            return Ok(frame);
+            while let Some(res) = input.next().await {
+                match res {
+                    Ok(chunk) => {
+                        buffer.extend_from_slice(&chunk);
+
+                        loop {
+                            match self.parse(&mut buffer) {
+                                Ok(Some(frame)) => yield Ok(frame),
+                                Ok(None) => break,
+                                Err(err) => yield Err(err.extend()),
+                            }
                         }
-                    }
+                    },
+                    Err(err) => yield Err(Error::Other(err)),
+                }
+            }
+
+            self.end_of_input();
+            loop {
+                match self.parse(&mut buffer) {
+                    Ok(Some(frame)) => yield Ok(frame),
+                    Ok(None) => break,
+                    Err(err) => yield Err(err.extend()),
+                }
+            }
 
-                    TsPayload::Section(_) => println!("section"),
-                    TsPayload::Null(_) => println!("null"),
-                    TsPayload::Raw(raw) => {
-                        if !self.ignore {
-                            self.body.put_slice(&raw);
+            while let Some(frame) = self.flush() {
+                yield Ok(frame);
+            }
+        }
+    }
+}
+
+/// The demuxer as a `Service` yielding every [`Mpeg2TsDemuxEvent`], see
+/// [`Mpeg2TsDemux::into_events`].
+pub struct Mpeg2TsDemuxEvents<S: FrameSource = ()>(Mpeg2TsDemux<S>);
+
+impl<S: FrameSource + Clone, E: flowly::Error> Service<Result<Bytes, E>> for Mpeg2TsDemuxEvents<S> {
+    type Out = Result<Mpeg2TsDemuxEvent<S>, Error<E>>;
+
+    fn handle(
+        self,
+        input: impl futures::Stream<Item = Result<Bytes, E>> + Send,
+    ) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut demux = self.0;
+
+        async_stream::stream! {
+            let mut input = pin!(input);
+            let mut buffer = BytesMut::new();
+
+            while let Some(res) = input.next().await {
+                match res {
+                    Ok(chunk) => {
+                        buffer.extend_from_slice(&chunk);
+
+                        loop {
+                            match demux.parse_event(&mut buffer) {
+                                Ok(Some(event)) => yield Ok(event),
+                                Ok(None) => break,
+                                Err(err) => yield Err(err.extend()),
+                            }
                         }
-                    }
+                    },
+                    Err(err) => yield Err(Error::Other(err)),
+                }
+            }
+
+            demux.end_of_input();
+            loop {
+                match demux.parse_event(&mut buffer) {
+                    Ok(Some(event)) => yield Ok(event),
+                    Ok(None) => break,
+                    Err(err) => yield Err(err.extend()),
                 }
             }
+
+            while let Some(event) = demux.flush_event() {
+                yield Ok(event);
+            }
         }
+    }
+}
 
-        self.mappings.replace(reader.into_mappings());
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpegts::{
+        stream_id::StreamId,
+        ts::{
+            Descriptor, PesHeader, ProgramAssociation, Psi, PsiTable, PsiTableHeader,
+            PsiTableSyntax,
+        },
+        version::VersionNumber,
+    };
+    use flowly::{DataFrame, EncodedFrame, Frame};
 
-        Ok(None)
+    /// Wraps a payload into a TS packet starting a payload unit, padded with 0xFF.
+    fn packet(pid: u16, cc: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x10 | cc];
+        packet.extend_from_slice(payload);
+        packet.resize(TsPacket::SIZE, 0xFF);
+        packet
+    }
+
+    fn psi_packet<T>(pid: u16, cc: u8, item: &T) -> Vec<u8>
+    where
+        Mpeg2tsParser: Io<T>,
+    {
+        let mut payload = BytesMut::new();
+        Mpeg2tsParser::new().serialize(item, &mut payload).unwrap();
+        packet(pid, cc, &payload)
+    }
+
+    fn pat() -> Pat {
+        Pat {
+            transport_stream_id: 1,
+            version_number: VersionNumber::new(),
+            table: vec![ProgramAssociation {
+                program_num: 1,
+                program_map_pid: Pid::new(0x1000).unwrap(),
+            }],
+        }
+    }
+
+    fn pmt(program_info: Vec<Descriptor>, stream_type: StreamType, pid: u16) -> Pmt {
+        Pmt {
+            program_num: 1,
+            pcr_pid: None,
+            version_number: VersionNumber::new(),
+            program_info,
+            es_info: vec![EsInfo {
+                stream_type,
+                elementary_pid: Pid::new(pid).unwrap(),
+                descriptors: Vec::new(),
+            }],
+        }
+    }
+
+    /// Wraps a payload into a TS packet, filling it up with adaptation field stuffing.
+    fn ts_packet(pid: u16, cc: u8, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let flags = if unit_start { 0x40 } else { 0 };
+        let mut packet = vec![0x47, flags | (pid >> 8) as u8, pid as u8, 0x10 | cc];
+
+        let stuffing = TsPacket::SIZE - 4 - payload.len();
+        if stuffing > 0 {
+            packet[3] |= 0x20;
+            packet.push(stuffing as u8 - 1);
+            if stuffing > 1 {
+                packet.push(0);
+                packet.resize(4 + stuffing, 0xFF);
+            }
+        }
+
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A PES header with a DTS if it differs from the PTS, bounded to `data_len` bytes if given.
+    fn pes(stream_id: u8, pts: Option<u64>, dts: Option<u64>, data_len: Option<usize>) -> BytesMut {
+        let ts = |x| Timestamp::<PtsDts>::new(x).unwrap();
+        let dts = dts.filter(|&dts| Some(dts) != pts);
+        let header_len = 3 + 5 * (pts.is_some() as usize + dts.is_some() as usize);
+
+        let header = PesHeader {
+            stream_id: StreamId::new(stream_id),
+            priority: false,
+            data_alignment_indicator: true,
+            copyright: false,
+            original_or_copy: false,
+            pts: pts.map(ts),
+            dts: dts.map(ts),
+            escr: None,
+            es_rate: None,
+            dsm_trick_mode: None,
+            additional_copy_info: None,
+            previous_pes_packet_crc: None,
+            extension: None,
+            stuffing_len: 0,
+            packet_len: data_len.map_or(0, |len| (header_len + len) as u16),
+        };
+
+        let mut buf = BytesMut::new();
+        Mpeg2tsParser::new().serialize(&header, &mut buf).unwrap();
+        buf
+    }
+
+    /// A bounded PES packet in a single TS packet.
+    fn pes_packet(pid: u16, stream_id: u8, pts: u64, data: &[u8]) -> Vec<u8> {
+        let mut payload = pes(stream_id, Some(pts), None, Some(data.len()));
+        payload.extend_from_slice(data);
+        packet(pid, 0, &payload)
+    }
+
+    /// An unbounded video PES packet starting with `data`.
+    fn video_pes(pid: u16, cc: u8, pts: Option<u64>, dts: Option<u64>, data: &[u8]) -> Vec<u8> {
+        let mut payload = pes(0xE0, pts, dts, None);
+        payload.extend_from_slice(data);
+        ts_packet(pid, cc, true, &payload)
+    }
+
+    /// Payload of an H.264 slice, first in its picture.
+    fn slice(nal_header: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 1, nal_header, 0x88];
+        data.resize(len, 0x5A);
+        data
+    }
+
+    /// ADTS frame of `len` bytes, AAC LC at 48 kHz stereo.
+    fn adts(len: usize) -> Vec<u8> {
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x4C,
+            0x80,
+            (len >> 3) as u8,
+            (len << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.resize(len, 0);
+        frame
+    }
+
+    fn sdt() -> Psi {
+        Psi {
+            tables: vec![PsiTable {
+                header: PsiTableHeader {
+                    table_id: 0x42,
+                    private_bit: true,
+                    syntax_section_indicator: true,
+                },
+                syntax: Some(PsiTableSyntax {
+                    table_id_extension: 1,
+                    version_number: VersionNumber::new(),
+                    current_next_indicator: true,
+                    section_number: 0,
+                    last_section_number: 0,
+                    table_data: Bytes::from_static(b"\xff\x01\xff"),
+                }),
+                private_data: Bytes::new(),
+            }],
+        }
+    }
+
+    fn splice_info() -> Psi {
+        Psi {
+            tables: vec![PsiTable {
+                header: PsiTableHeader {
+                    table_id: 0xFC,
+                    private_bit: false,
+                    syntax_section_indicator: false,
+                },
+                syntax: None,
+                private_data: Bytes::from_static(b"\x00\x00\x00\x00\x00\x00\xff\xf0\x00\x00"),
+            }],
+        }
+    }
+
+    fn sections(stream: &[Vec<u8>]) -> Vec<(u16, u8, Bytes)> {
+        let config = Mpeg2TsDemuxConfig {
+            events: true,
+            ..Default::default()
+        };
+
+        let mut demux = Mpeg2TsDemux::with_config((), 0, config);
+        let mut src = BytesMut::from(&stream.concat()[..]);
+        let mut sections = Vec::new();
+        demux.end_of_input();
+
+        while let Some(event) = demux.parse_event(&mut src).unwrap() {
+            if let Mpeg2TsDemuxEvent::Section {
+                pid,
+                table_id,
+                mut data,
+                ..
+            } = event
+            {
+                sections.push((pid, table_id, data.remove(0)));
+            }
+        }
+
+        sections
+    }
+
+    #[test]
+    fn si_and_scte35_sections_reported() {
+        let sections = sections(&[
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(
+                0x1000,
+                0,
+                &pmt(Vec::new(), StreamType::Dts8ChannelLosslessAudio, 0x102),
+            ),
+            psi_packet(0x11, 0, &sdt()),
+            psi_packet(0x102, 0, &splice_info()),
+            psi_packet(0x11, 1, &sdt()),
+            psi_packet(0x102, 1, &splice_info()),
+        ]);
+
+        let sdt = (0x11, 0x42, Bytes::from_static(b"\xff\x01\xff"));
+        let splice = (0x102, 0xFC, splice_info().tables[0].private_data.clone());
+
+        // the unchanged SDT is reported once, every splice_info_section is
+        assert_eq!(sections, [sdt, splice.clone(), splice]);
+    }
+
+    #[test]
+    fn hdmv_stream_type_0x86_is_pes() {
+        let hdmv = Descriptor {
+            tag: 0x05,
+            data: Bytes::from_static(b"HDMV"),
+        };
+
+        let mut parser = Mpeg2tsParser::new();
+        for packet in [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(
+                0x1000,
+                0,
+                &pmt(vec![hdmv], StreamType::Dts8ChannelLosslessAudio, 0x102),
+            ),
+        ] {
+            let _: TsPacket = parser.parse(&mut &packet[..]).unwrap();
+        }
+
+        let pes = packet(0x102, 0, b"\x00\x00\x01\xfd\x00\x00\x80\x00\x00");
+        let pkt: TsPacket = parser.parse(&mut &pes[..]).unwrap();
+        assert!(matches!(pkt.payload, Some(TsPayload::Pes(_))));
+    }
+
+    /// An ADTS stream on PID 0x101 with two frames in one PES packet.
+    fn audio_stream() -> [Vec<u8>; 3] {
+        [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::AdtsAac, 0x101)),
+            pes_packet(0x101, 0xC0, 90_000, &[adts(60), adts(80)].concat()),
+        ]
+    }
+
+    fn frames(demux: &mut Mpeg2TsDemux, src: &[u8]) -> Vec<Mpeg2TsDemuxFrame<()>> {
+        let mut src = BytesMut::from(src);
+        demux.end_of_input();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = demux.parse(&mut src).unwrap() {
+            frames.push(frame);
+        }
+
+        frames.extend(std::iter::from_fn(|| demux.flush()));
+        frames
+    }
+
+    #[test]
+    fn audio_frames_emitted() {
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let frames = frames(&mut demux, &audio_stream().concat());
+
+        assert_eq!(frames.len(), 2);
+        for (frame, (pts, len)) in frames.iter().zip([(1_000_000, 60), (1_021_333, 80)]) {
+            let Mpeg2TsDemuxFrame::Audio(audio) = frame else {
+                panic!("not an audio frame");
+            };
+
+            assert_eq!(frame.codec(), Fourcc::AUDIO_AAC);
+            assert_eq!((audio.pts, audio.payload.len()), (pts, len));
+            assert_eq!((audio.sample_rate, audio.samples), (48000, 1024));
+            assert_eq!(audio.channel_layout.count(), 2);
+            assert_eq!(audio.config.as_deref(), Some(&[0x11, 0x90][..]));
+        }
+    }
+
+    #[test]
+    fn m2ts_arrival_time_reported() {
+        let mut src = Vec::new();
+        for (i, packet) in audio_stream().iter().enumerate() {
+            // the copy permission bits are not part of the arrival time
+            let header = 0xC000_0000 | (1000 * i as u32);
+            src.extend_from_slice(&header.to_be_bytes());
+            src.extend_from_slice(packet);
+        }
+
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let frames = frames(&mut demux, &src);
+
+        assert_eq!(demux.format(), Some(PacketFormat::M2ts));
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|x| x.arrival_time() == Some(2000)));
+    }
+
+    #[test]
+    fn rs_parity_dropped() {
+        let mut src = Vec::new();
+        for packet in audio_stream() {
+            src.extend_from_slice(&packet);
+            src.extend_from_slice(&[0; 16]);
+        }
+
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let frames = frames(&mut demux, &src);
+
+        assert_eq!(demux.format(), Some(PacketFormat::Rs));
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|x| x.arrival_time().is_none()));
+    }
+
+    #[test]
+    fn pes_completion() {
+        let stream = [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::H264, 0x100)),
+            video_pes(0x100, 0, Some(9000), None, &slice(0x65, 100)),
+            ts_packet(0x100, 1, false, &[0xAA; 50]),
+            video_pes(0x100, 2, Some(12000), None, &slice(0x41, 100)),
+        ];
+
+        // a bounded PES packet is complete as soon as its data arrived
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let mut src = BytesMut::from(&audio_stream().concat()[..]);
+        demux.end_of_input();
+        assert!(demux.parse(&mut src).unwrap().is_some());
+
+        // an unbounded one once the next one starts, or at the end of the stream
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let mut src = BytesMut::from(&stream.concat()[..]);
+        demux.end_of_input();
+
+        let frame = demux.parse(&mut src).unwrap().unwrap();
+        assert_eq!(frame.pts(), 100_000);
+        assert_eq!(frame.payload().len(), 150);
+        assert!(demux.parse(&mut src).unwrap().is_none());
+
+        let frame = demux.flush().unwrap();
+        assert_eq!(frame.pts(), 133_333);
+        assert!(demux.flush().is_none());
+    }
+
+    #[test]
+    fn bad_pes_header_closes_pending_packet() {
+        let mut bad_start_code = vec![0, 0, 2, 0xE0, 0, 0];
+        bad_start_code.extend_from_slice(&[0xCC; 20]);
+
+        let stream = [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::H264, 0x100)),
+            video_pes(0x100, 0, Some(9000), None, &slice(0x65, 100)),
+            ts_packet(0x100, 1, false, &[0xAA; 50]),
+            ts_packet(0x100, 2, true, &bad_start_code),
+            ts_packet(0x100, 3, false, &[0xBB; 50]),
+            video_pes(0x100, 4, Some(18000), None, &slice(0x41, 100)),
+        ];
+
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let mut src = BytesMut::from(&stream.concat()[..]);
+        demux.end_of_input();
+
+        let mut frames = Vec::new();
+        let mut errors = 0;
+        loop {
+            match demux.parse(&mut src) {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break,
+                Err(_) => errors += 1,
+            }
+        }
+
+        frames.extend(demux.flush());
+
+        assert_eq!(errors, 1);
+        let frames: Vec<_> = frames
+            .iter()
+            .map(|x| (x.pts(), x.payload().len()))
+            .collect();
+        assert_eq!(frames, [(100_000, 150), (200_000, 100)]);
+    }
+
+    #[test]
+    fn last_frame_emitted_at_end_of_input() {
+        let stream = [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::H264, 0x100)),
+            video_pes(0x100, 0, Some(9000), None, &slice(0x65, 100)),
+            video_pes(0x100, 1, Some(12000), None, &slice(0x41, 100)),
+            ts_packet(0x100, 2, false, &[0xAA; 50]),
+        ];
+
+        let input = stream
+            .iter()
+            .map(|packet| Ok::<_, std::io::Error>(Bytes::copy_from_slice(packet)));
+
+        let demux = Mpeg2TsDemux::<()>::new((), 0);
+        let frames: Vec<_> =
+            futures::executor::block_on(demux.handle(futures::stream::iter(input)).collect());
+
+        let frames: Vec<_> = frames
+            .into_iter()
+            .map(|x| x.unwrap())
+            .map(|x| (x.pts(), x.payload().len()))
+            .collect();
+
+        // the last PES packet is unbounded and held until the input ends
+        assert_eq!(frames, [(100_000, 100), (133_333, 150)]);
+    }
+
+    #[test]
+    fn only_selected_programs_demuxed() {
+        let pat = Pat {
+            table: vec![
+                ProgramAssociation {
+                    program_num: 1,
+                    program_map_pid: Pid::new(0x1000).unwrap(),
+                },
+                ProgramAssociation {
+                    program_num: 2,
+                    program_map_pid: Pid::new(0x1001).unwrap(),
+                },
+            ],
+            ..pat()
+        };
+
+        let pmt2 = Pmt {
+            program_num: 2,
+            ..pmt(Vec::new(), StreamType::AdtsAac, 0x201)
+        };
+
+        let [_, _, audio] = audio_stream();
+        let mut audio2 = audio.clone();
+        audio2[1..3].copy_from_slice(&[0x42, 0x01]);
+
+        // program 1 is scrambled, its PES headers cannot be read
+        let mut scrambled = audio.clone();
+        scrambled[4..].fill(0x5A);
+
+        let stream = [
+            psi_packet(Pid::PAT, 0, &pat),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::AdtsAac, 0x101)),
+            psi_packet(0x1001, 0, &pmt2),
+            scrambled,
+            audio2,
+        ];
+
+        let config = Mpeg2TsDemuxConfig {
+            programs: ProgramSelection::Only(vec![2]),
+            ..Default::default()
+        };
+
+        let mut demux = Mpeg2TsDemux::with_config((), 0, config);
+        let frames = frames(&mut demux, &stream.concat());
+
+        assert_eq!(demux.programs(), [2]);
+        assert!(demux.program_streams(1).is_empty());
+
+        let streams = demux.program_streams(2);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(
+            (streams[0].pid, streams[0].codec),
+            (0x201, Fourcc::AUDIO_AAC)
+        );
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|x| x.source().pid == 0x201));
+    }
+
+    #[test]
+    fn video_timestamps() {
+        let stream = [
+            psi_packet(Pid::PAT, 0, &pat()),
+            psi_packet(0x1000, 0, &pmt(Vec::new(), StreamType::H264, 0x100)),
+            // I P B in decode order, then a PES packet without timestamps
+            video_pes(0x100, 0, Some(12000), Some(9000), &slice(0x65, 100)),
+            video_pes(0x100, 1, Some(18000), Some(12000), &slice(0x41, 100)),
+            video_pes(0x100, 2, Some(15000), Some(15000), &slice(0x41, 100)),
+            video_pes(0x100, 3, None, None, &slice(0x41, 100)),
+        ];
+
+        let mut demux = Mpeg2TsDemux::new((), 0);
+        let frames: Vec<_> = frames(&mut demux, &stream.concat())
+            .iter()
+            .map(|x| (x.pts(), x.timestamp()))
+            .collect();
+
+        assert_eq!(
+            frames,
+            [
+                (133_333, 100_000),
+                (200_000, 133_333),
+                (166_666, 166_666),
+                (200_000, 200_000)
+            ]
+        );
     }
 }
@@ -0,0 +1,293 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::demux::nal::{NalClass, NalCodec};
+
+/// Default DTS step between access units in 90 kHz ticks, 30 frames per second.
+const DEFAULT_DURATION: u64 = 3000;
+
+/// Timing and flags a video frame takes from the PES packet it starts in.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PesInfo {
+    /// Unwrapped timestamps in 90 kHz ticks.
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    pub random_access: bool,
+    pub arrival_time: Option<u32>,
+    pub corrupt: bool,
+}
+
+/// Fills in the timestamps of frames whose PES packet carries none, stepping
+/// from the last known ones by the DTS difference between frames.
+#[derive(Debug)]
+pub struct TimestampInterpolator {
+    /// Last known PTS and DTS, and frames since.
+    last: Option<(u64, u64)>,
+    since_last: u64,
+    duration: u64,
+}
+
+impl TimestampInterpolator {
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            since_last: 0,
+            duration: DEFAULT_DURATION,
+        }
+    }
+
+    /// Returns the PTS and DTS of the next frame, `None` until a PTS is known.
+    pub fn next(&mut self, pts: Option<u64>, dts: Option<u64>) -> (Option<u64>, Option<u64>) {
+        if let Some(pts) = pts {
+            let dts = dts.unwrap_or(pts);
+
+            if let Some((_, last_dts)) = self.last
+                && dts > last_dts
+            {
+                self.duration = (dts - last_dts) / self.since_last.max(1);
+            }
+
+            self.last = Some((pts, dts));
+            self.since_last = 1;
+
+            return (Some(pts), Some(dts));
+        }
+
+        let Some((pts, dts)) = self.last else {
+            return (None, None);
+        };
+
+        let step = self.since_last * self.duration;
+        self.since_last += 1;
+
+        (Some(pts + step), Some(dts + step))
+    }
+}
+
+/// A single coded picture.
+#[derive(Debug, Clone)]
+pub struct AccessUnit {
+    pub info: PesInfo,
+    pub data: Bytes,
+}
+
+/// Splits the PES payloads of one H.264 or H.265 PID into access units.
+///
+/// A new access unit starts at an access unit delimiter, parameter set or
+/// prefix SEI following a slice, or at a slice that starts a picture. The
+/// timestamps of a PES packet apply to the first access unit that starts in
+/// it, the following ones get timestamps interpolated from the DTS step.
+#[derive(Debug)]
+pub struct AccessUnitSplitter {
+    codec: NalCodec,
+    buf: BytesMut,
+
+    /// Position up to which the buffer was searched for start codes.
+    scanned: usize,
+
+    /// The access unit at the start of the buffer has a slice.
+    has_vcl: bool,
+
+    /// Info of the access unit at the start of the buffer.
+    current: PesInfo,
+
+    /// Buffer offset of the first byte of the latest PES packet and its info,
+    /// until an access unit starts in it.
+    pes: Option<(usize, PesInfo)>,
+    latest: PesInfo,
+    timestamps: TimestampInterpolator,
+}
+
+impl AccessUnitSplitter {
+    pub fn new(codec: NalCodec) -> Self {
+        Self {
+            codec,
+            buf: BytesMut::new(),
+            scanned: 0,
+            has_vcl: false,
+            current: PesInfo::default(),
+            pes: None,
+            latest: PesInfo::default(),
+            timestamps: TimestampInterpolator::new(),
+        }
+    }
+
+    /// Appends the payload of a PES packet and returns every complete access unit.
+    pub fn push(&mut self, info: PesInfo, data: &[u8], out: &mut Vec<AccessUnit>) {
+        if self.buf.is_empty() {
+            self.current = info;
+            self.pes = None;
+        } else {
+            self.current.corrupt |= info.corrupt;
+            self.pes = Some((self.buf.len(), info));
+        }
+
+        self.latest = info;
+        self.buf.extend_from_slice(data);
+
+        while let Some(pos) = self.buf[self.scanned..]
+            .windows(3)
+            .position(|w| w == [0, 0, 1])
+            .map(|pos| self.scanned + pos)
+        {
+            let nal = &self.buf[pos + 3..];
+            if nal.len() < 3 {
+                // classify once the header is complete
+                self.scanned = pos;
+                return;
+            }
+
+            let class = self.codec.classify(nal);
+            let starts = match class {
+                NalClass::Vcl { first } => first && self.has_vcl,
+                NalClass::Prefix => self.has_vcl,
+                NalClass::Other => false,
+            };
+
+            let mut pos = pos;
+            if starts {
+                // the zero byte of a 4-byte start code belongs to the next access unit
+                let end = if pos > 0 && self.buf[pos - 1] == 0 {
+                    pos - 1
+                } else {
+                    pos
+                };
+
+                self.emit(end, pos, out);
+                pos -= end;
+            }
+
+            if let NalClass::Vcl { .. } = class {
+                self.has_vcl = true;
+            }
+
+            self.scanned = pos + 3;
+        }
+
+        // a start code may straddle the end of the buffer
+        self.scanned = self.scanned.max(self.buf.len().saturating_sub(2));
+    }
+
+    /// Returns the access unit still held in the buffer.
+    pub fn flush(&mut self, out: &mut Vec<AccessUnit>) {
+        if !self.buf.is_empty() {
+            self.emit(self.buf.len(), self.buf.len(), out);
+        }
+
+        self.scanned = 0;
+    }
+
+    /// Emits the first `end` bytes of the buffer as an access unit, the next
+    /// one having its first start code at `start`.
+    fn emit(&mut self, end: usize, start: usize, out: &mut Vec<AccessUnit>) {
+        let mut info = self.current;
+        (info.pts, info.dts) = self.timestamps.next(info.pts, info.dts);
+
+        out.push(AccessUnit {
+            info,
+            data: self.buf.split_to(end).freeze(),
+        });
+
+        self.has_vcl = false;
+        self.current = match self.pes {
+            Some((offset, info)) if offset <= start => {
+                self.pes = None;
+                info
+            }
+
+            pes => {
+                self.pes = pes.map(|(offset, info)| (offset - end, info));
+                PesInfo {
+                    pts: None,
+                    dts: None,
+                    random_access: false,
+                    ..self.latest
+                }
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const AUD: &[u8] = &[0x09, 0xF0];
+    const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1F];
+    const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x21];
+    const IDR_SECOND_SLICE: &[u8] = &[0x65, 0x08, 0x84, 0x21];
+    const SLICE: &[u8] = &[0x41, 0x9A, 0x02, 0x11];
+
+    /// Joins NAL units into Annex B data, with 4-byte start codes.
+    fn annex_b(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+            .collect()
+    }
+
+    fn info(pts: Option<u64>) -> PesInfo {
+        PesInfo {
+            pts,
+            ..Default::default()
+        }
+    }
+
+    fn split(pes: &[(PesInfo, Vec<u8>)]) -> Vec<AccessUnit> {
+        let mut splitter = AccessUnitSplitter::new(NalCodec::H264);
+        let mut out = Vec::new();
+
+        for (info, data) in pes {
+            splitter.push(*info, data, &mut out);
+        }
+
+        splitter.flush(&mut out);
+        out
+    }
+
+    #[test]
+    fn several_pictures_in_one_pes() {
+        let pes = [
+            annex_b(&[SPS, PPS, IDR, IDR_SECOND_SLICE]),
+            annex_b(&[SLICE]),
+            annex_b(&[AUD, SLICE]),
+        ];
+
+        let units = split(&[(info(Some(9000)), pes.concat())]);
+
+        assert_eq!(units.len(), 3);
+        for (unit, (pts, data)) in units.iter().zip([9000, 12000, 15000].iter().zip(&pes)) {
+            assert_eq!(unit.info.pts, Some(*pts));
+            assert_eq!(unit.info.dts, Some(*pts));
+            assert_eq!(&unit.data[..], &data[..]);
+        }
+    }
+
+    #[test]
+    fn picture_split_across_pes() {
+        let first = annex_b(&[AUD, SPS, PPS, IDR, IDR_SECOND_SLICE]);
+        let second = annex_b(&[AUD, SLICE]);
+
+        let units = split(&[
+            (info(Some(9000)), first[..10].to_vec()),
+            (info(None), first[10..].to_vec()),
+            (info(Some(12600)), second.clone()),
+        ]);
+
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].info.pts, Some(9000));
+        assert_eq!(&units[0].data[..], &first[..]);
+        assert_eq!(units[1].info.pts, Some(12600));
+        assert_eq!(&units[1].data[..], &second[..]);
+    }
+
+    #[test]
+    fn timestamps_interpolated_from_dts_step() {
+        let units = split(&[
+            (info(Some(1000)), annex_b(&[IDR])),
+            (info(Some(4600)), annex_b(&[SLICE, SLICE, SLICE])),
+        ]);
+
+        let pts: Vec<_> = units.iter().map(|x| x.info.pts.unwrap()).collect();
+        assert_eq!(pts, [1000, 4600, 8200, 11800]);
+    }
+}
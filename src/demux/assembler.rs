@@ -0,0 +1,302 @@
+use std::collections::{HashMap, VecDeque, hash_map::Entry};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    demux::nal::NalCodec,
+    mpegts::{
+        pid::Pid,
+        ts::{Pes, PesHeader},
+    },
+};
+
+/// A complete PES packet reassembled from the TS packets of one PID.
+#[derive(Debug, Clone)]
+pub struct PesPacket {
+    pub pid: Pid,
+
+    /// `random_access_indicator` of the TS packet that started the PES packet.
+    pub random_access: bool,
+
+    /// Arrival time stamp of the TS packet that started the PES packet, in
+    /// 27 MHz units, for M2TS input.
+    pub arrival_time: Option<u32>,
+
+    /// Packets of the PES packet were lost.
+    pub corrupt: bool,
+
+    pub pes: Pes<Bytes>,
+}
+
+#[derive(Debug)]
+struct PesBuffer {
+    header: PesHeader,
+    random_access: bool,
+    arrival_time: Option<u32>,
+    corrupt: bool,
+    data: BytesMut,
+}
+
+/// Reassembles PES packets, keeping a separate buffer per elementary PID.
+///
+/// Bounded PES packets are complete once `PES_packet_length` bytes arrived,
+/// unbounded ones when the next PES packet starts on the same PID or, for
+/// video PIDs with a known codec, as soon as the data ends with a NAL unit
+/// that closes the access unit.
+#[derive(Debug, Default)]
+pub struct PesAssembler {
+    buffers: HashMap<Pid, PesBuffer>,
+    codecs: HashMap<Pid, NalCodec>,
+}
+
+impl PesAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the codec used to detect the end of unbounded video PES packets on `pid`.
+    pub fn set_codec(&mut self, pid: Pid, codec: NalCodec) {
+        self.codecs.insert(pid, codec);
+    }
+
+    /// Starts a new PES packet on `pid`, completing the pending one.
+    pub fn start(
+        &mut self,
+        pid: Pid,
+        header: PesHeader,
+        random_access: bool,
+        arrival_time: Option<u32>,
+        data: &[u8],
+        out: &mut VecDeque<PesPacket>,
+    ) {
+        if let Some(buf) = self.buffers.remove(&pid) {
+            out.push_back(Self::complete(pid, buf));
+        }
+
+        let capacity = header.data_len().unwrap_or(64 * 1024);
+        let mut buf = PesBuffer {
+            header,
+            random_access,
+            arrival_time,
+            corrupt: false,
+            data: BytesMut::with_capacity(capacity),
+        };
+
+        buf.data.extend_from_slice(data);
+        self.buffers.insert(pid, buf);
+        self.check_complete(pid, out);
+    }
+
+    /// Appends a continuation payload to the pending PES packet on `pid`.
+    ///
+    /// Payloads without a started PES packet are dropped.
+    pub fn append(&mut self, pid: Pid, data: &[u8], out: &mut VecDeque<PesPacket>) {
+        if let Some(buf) = self.buffers.get_mut(&pid) {
+            buf.data.extend_from_slice(data);
+            self.check_complete(pid, out);
+        }
+    }
+
+    /// Completes the pending PES packet on `pid`, which ends where a PES
+    /// packet that could not be read starts.
+    pub fn close(&mut self, pid: Pid, out: &mut VecDeque<PesPacket>) {
+        if let Some(buf) = self.buffers.remove(&pid) {
+            out.push_back(Self::complete(pid, buf));
+        }
+    }
+
+    /// Marks the pending PES packet on `pid` as corrupt after packet loss.
+    pub fn mark_corrupt(&mut self, pid: Pid) {
+        if let Some(buf) = self.buffers.get_mut(&pid) {
+            buf.corrupt = true;
+        }
+    }
+
+    /// Completes every pending PES packet, in PID order.
+    pub fn flush(&mut self, out: &mut VecDeque<PesPacket>) {
+        let mut buffers: Vec<_> = self.buffers.drain().collect();
+        buffers.sort_by_key(|(pid, _)| *pid);
+
+        for (pid, buf) in buffers {
+            out.push_back(Self::complete(pid, buf));
+        }
+    }
+
+    fn check_complete(&mut self, pid: Pid, out: &mut VecDeque<PesPacket>) {
+        let Entry::Occupied(entry) = self.buffers.entry(pid) else {
+            return;
+        };
+
+        let buf = entry.get();
+        let len = match buf.header.data_len() {
+            Some(len) if buf.data.len() >= len => len,
+            Some(_) => return,
+            None => match self.codecs.get(&pid) {
+                Some(codec) if codec.ends_access_unit(&buf.data) => buf.data.len(),
+                _ => return,
+            },
+        };
+
+        let mut buf = entry.remove();
+        buf.data.truncate(len);
+        out.push_back(Self::complete(pid, buf));
+    }
+
+    fn complete(pid: Pid, buf: PesBuffer) -> PesPacket {
+        PesPacket {
+            pid,
+            random_access: buf.random_access,
+            arrival_time: buf.arrival_time,
+            corrupt: buf.corrupt,
+            pes: Pes {
+                header: buf.header,
+                data: buf.data.freeze(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpegts::stream_id::StreamId;
+
+    fn header(data_len: usize) -> PesHeader {
+        PesHeader {
+            stream_id: StreamId::new(0xE0),
+            priority: false,
+            data_alignment_indicator: false,
+            copyright: false,
+            original_or_copy: false,
+            pts: None,
+            dts: None,
+            escr: None,
+            es_rate: None,
+            dsm_trick_mode: None,
+            additional_copy_info: None,
+            previous_pes_packet_crc: None,
+            extension: None,
+            stuffing_len: 0,
+            packet_len: if data_len == 0 {
+                0
+            } else {
+                3 + data_len as u16
+            },
+        }
+    }
+
+    fn data(packets: &VecDeque<PesPacket>) -> Vec<&[u8]> {
+        packets.iter().map(|p| &p.pes.data[..]).collect()
+    }
+
+    #[test]
+    fn bounded_packet_completes_at_its_length() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let pid = Pid(0x100);
+
+        assembler.start(pid, header(6), true, Some(7), b"abc", &mut out);
+        assert!(out.is_empty());
+
+        // the rest of the last TS packet is dropped
+        assembler.append(pid, b"defgh", &mut out);
+        assert_eq!(data(&out), [b"abcdef"]);
+        assert!(out[0].random_access);
+        assert_eq!(out[0].arrival_time, Some(7));
+
+        // nothing is pending any more
+        assembler.append(pid, b"ijk", &mut out);
+        assembler.flush(&mut out);
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    fn unbounded_packet_completes_at_next_start() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let (video, audio) = (Pid(0x100), Pid(0x101));
+
+        assembler.start(video, header(0), false, None, b"ab", &mut out);
+        assembler.start(audio, header(0), false, None, b"xy", &mut out);
+        assembler.append(video, b"cd", &mut out);
+        assert!(out.is_empty());
+
+        assembler.start(video, header(0), false, None, b"ef", &mut out);
+        assert_eq!(data(&out), [b"abcd"]);
+        assert_eq!(out[0].pid, video);
+
+        assembler.flush(&mut out);
+        assert_eq!(data(&out), [&b"abcd"[..], b"ef", b"xy"]);
+    }
+
+    #[test]
+    fn unbounded_video_completes_at_access_unit_end() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let pid = Pid(0x100);
+        assembler.set_codec(pid, NalCodec::H264);
+
+        assembler.start(pid, header(0), false, None, b"\0\0\x01\x65\x88", &mut out);
+        assert!(out.is_empty());
+
+        // access unit delimiter closing the picture
+        assembler.append(pid, b"\0\0\0\x01\x09\xf0", &mut out);
+        assert_eq!(data(&out), [b"\0\0\x01\x65\x88\0\0\0\x01\x09\xf0"]);
+    }
+
+    #[test]
+    fn loss_marks_pending_packet_corrupt() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let pid = Pid(0x100);
+
+        // continuation without a start is dropped
+        assembler.append(pid, b"zz", &mut out);
+        assembler.mark_corrupt(pid);
+
+        assembler.start(pid, header(0), false, None, b"ab", &mut out);
+        assembler.mark_corrupt(pid);
+        assembler.start(pid, header(0), false, None, b"cd", &mut out);
+        assembler.flush(&mut out);
+
+        assert_eq!(data(&out), [b"ab", b"cd"]);
+        assert!(out[0].corrupt);
+        assert!(!out[1].corrupt);
+    }
+
+    #[test]
+    fn close_completes_pending_packet() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let pid = Pid(0x100);
+
+        assembler.start(pid, header(0), false, None, b"ab", &mut out);
+        assembler.close(pid, &mut out);
+        assert_eq!(data(&out), [b"ab"]);
+
+        // continuations of the unreadable packet are dropped
+        assembler.append(pid, b"cd", &mut out);
+        assembler.close(pid, &mut out);
+        assembler.flush(&mut out);
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    fn leading_delimiter_does_not_complete() {
+        let mut assembler = PesAssembler::new();
+        let mut out = VecDeque::new();
+        let pid = Pid(0x100);
+        assembler.set_codec(pid, NalCodec::H264);
+
+        // the first packet carries nothing but the delimiter opening the picture
+        assembler.start(pid, header(0), false, None, b"\0\0\0\x01\x09\xf0", &mut out);
+        assembler.append(pid, b"\0\0\x01\x65\x88", &mut out);
+        assert!(out.is_empty());
+
+        assembler.append(pid, b"\0\0\0\x01\x09\xf0", &mut out);
+        assert_eq!(
+            data(&out),
+            [b"\0\0\0\x01\x09\xf0\0\0\x01\x65\x88\0\0\0\x01\x09\xf0"]
+        );
+    }
+}
@@ -0,0 +1,625 @@
+use bytes::{Buf, Bytes, BytesMut};
+use flowly::Fourcc;
+
+use crate::frame::ChannelLayout;
+
+const ADTS_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Bit rates in kbit/s indexed by `[version 1 / 2][layer - 1][bitrate_index]`.
+const MPEG_BIT_RATES: [[[u32; 15]; 3]; 2] = [
+    [
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+        ],
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ],
+    ],
+    [
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+        ],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ],
+];
+
+const MPEG_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// AC-3 bit rates in kbit/s indexed by `frmsizecod / 2`.
+const AC3_BIT_RATES: [u32; 19] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+];
+
+/// Number of full-range channels indexed by the Dolby `acmod`.
+const DOLBY_CHANNELS: [u8; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+
+/// Audio codecs that the demuxer splits into frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// AAC in ADTS framing.
+    Aac,
+
+    /// MPEG-1/2 audio layers I, II and III.
+    Mpeg,
+
+    Ac3,
+    Eac3,
+}
+
+/// Header fields of a single audio frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFrameHeader {
+    /// Length of the frame in bytes, header included.
+    pub len: usize,
+    pub sample_rate: u32,
+    pub channel_layout: ChannelLayout,
+    pub samples: u32,
+
+    /// AudioSpecificConfig of ADTS frames.
+    pub aac_config: Option<[u8; 2]>,
+
+    /// `true` for E-AC-3 dependent substreams, which belong to the preceding frame.
+    pub dependent: bool,
+}
+
+/// An audio frame split out of a PES payload.
+#[derive(Debug, Clone)]
+pub struct AudioFrame {
+    /// Presentation timestamp in 90 kHz units.
+    pub pts: u64,
+    pub header: AudioFrameHeader,
+    pub data: Bytes,
+}
+
+impl AudioCodec {
+    pub fn fourcc(self) -> Fourcc {
+        match self {
+            AudioCodec::Aac => Fourcc::AUDIO_AAC,
+            AudioCodec::Mpeg => Fourcc::AUDIO_MP3,
+            AudioCodec::Ac3 => Fourcc::AUDIO_AC3,
+            AudioCodec::Eac3 => Fourcc::AUDIO_EAC3,
+        }
+    }
+
+    /// Number of bytes needed to parse a frame header.
+    fn header_len(self) -> usize {
+        match self {
+            AudioCodec::Aac => 7,
+            AudioCodec::Mpeg => 4,
+            AudioCodec::Ac3 | AudioCodec::Eac3 => 8,
+        }
+    }
+
+    /// Parses the frame header at the start of `data`.
+    ///
+    /// Returns `None` if `data` does not start with a valid header.
+    pub fn parse_header(self, data: &[u8]) -> Option<AudioFrameHeader> {
+        if data.len() < self.header_len() {
+            return None;
+        }
+
+        match self {
+            AudioCodec::Aac => parse_adts(data),
+            AudioCodec::Mpeg => parse_mpeg(data),
+            // DVB signals both through the same private stream type, tell them apart by bsid
+            AudioCodec::Ac3 | AudioCodec::Eac3 => match data.get(5)? >> 3 {
+                0..=10 => parse_ac3(data),
+                11..=16 => parse_eac3(data),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn parse_adts(data: &[u8]) -> Option<AudioFrameHeader> {
+    // syncword and layer
+    if data[0] != 0xFF || data[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+
+    let protection_absent = data[1] & 0x01 != 0;
+    let profile = data[2] >> 6;
+    let sample_rate_index = (data[2] >> 2) & 0x0F;
+    let sample_rate = *ADTS_SAMPLE_RATES.get(sample_rate_index as usize)?;
+    let channel_config = ((data[2] & 0x01) << 2) | (data[3] >> 6);
+    let len =
+        (((data[3] & 0x03) as usize) << 11) | ((data[4] as usize) << 3) | (data[5] >> 5) as usize;
+    let raw_data_blocks = (data[6] & 0x03) as u32 + 1;
+
+    if len < if protection_absent { 7 } else { 9 } {
+        return None;
+    }
+
+    let channel_layout = match channel_config {
+        // 0 means the layout is given by a program config element in the payload
+        0..=5 => ChannelLayout {
+            channels: channel_config,
+            lfe: false,
+        },
+        6 => ChannelLayout {
+            channels: 5,
+            lfe: true,
+        },
+        _ => ChannelLayout {
+            channels: 7,
+            lfe: true,
+        },
+    };
+
+    // audioObjectType, samplingFrequencyIndex, channelConfiguration and an
+    // all-zero GASpecificConfig
+    let config = (u16::from(profile + 1) << 11)
+        | (u16::from(sample_rate_index) << 7)
+        | (u16::from(channel_config) << 3);
+
+    Some(AudioFrameHeader {
+        len,
+        sample_rate,
+        channel_layout,
+        samples: 1024 * raw_data_blocks,
+        aac_config: Some(config.to_be_bytes()),
+        dependent: false,
+    })
+}
+
+fn parse_mpeg(data: &[u8]) -> Option<AudioFrameHeader> {
+    if data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    // 0: MPEG-2.5, 1: reserved, 2: MPEG-2, 3: MPEG-1
+    let version = (data[1] >> 3) & 0x03;
+    // 1: layer III, 2: layer II, 3: layer I
+    let layer = match (data[1] >> 1) & 0x03 {
+        0 => return None,
+        n => 4 - n,
+    };
+
+    let bit_rate_index = (data[2] >> 4) as usize;
+    let sample_rate_index = ((data[2] >> 2) & 0x03) as usize;
+    let padding = ((data[2] >> 1) & 0x01) as u32;
+
+    if version == 1 || bit_rate_index == 0 || bit_rate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let bit_rate = MPEG_BIT_RATES[!mpeg1 as usize][layer as usize - 1][bit_rate_index] * 1000;
+    // MPEG-2 halves and MPEG-2.5 quarters the MPEG-1 sample rates
+    let sample_rate = MPEG_SAMPLE_RATES[sample_rate_index] >> (3 - version).min(2);
+
+    let (samples, len) = match layer {
+        1 => (384, (12 * bit_rate / sample_rate + padding) * 4),
+        2 => (1152, 144 * bit_rate / sample_rate + padding),
+        _ if mpeg1 => (1152, 144 * bit_rate / sample_rate + padding),
+        _ => (576, 72 * bit_rate / sample_rate + padding),
+    };
+
+    Some(AudioFrameHeader {
+        len: len as usize,
+        sample_rate,
+        channel_layout: ChannelLayout {
+            channels: if data[3] >> 6 == 0x03 { 1 } else { 2 },
+            lfe: false,
+        },
+        samples,
+        aac_config: None,
+        dependent: false,
+    })
+}
+
+fn parse_ac3(data: &[u8]) -> Option<AudioFrameHeader> {
+    if data[0] != 0x0B || data[1] != 0x77 {
+        return None;
+    }
+
+    let fscod = data[4] >> 6;
+    let frmsizecod = (data[4] & 0x3F) as usize;
+    let bit_rate = *AC3_BIT_RATES.get(frmsizecod / 2)?;
+
+    let (sample_rate, words) = match fscod {
+        0 => (48000, 2 * bit_rate),
+        1 => (44100, bit_rate * 96_000 / 44_100 + (frmsizecod & 1) as u32),
+        2 => (32000, 3 * bit_rate),
+        _ => return None,
+    };
+
+    let bits = u16::from_be_bytes([data[6], data[7]]);
+    let acmod = (bits >> 13) as u8;
+
+    // cmixlev, surmixlev and dsurmod precede lfeon depending on acmod
+    let mut pos = 3;
+    if acmod & 0x01 != 0 && acmod != 1 {
+        pos += 2;
+    }
+    if acmod & 0x04 != 0 {
+        pos += 2;
+    }
+    if acmod == 2 {
+        pos += 2;
+    }
+
+    let lfeon = (bits >> (15 - pos)) & 0x01;
+
+    Some(AudioFrameHeader {
+        len: words as usize * 2,
+        sample_rate,
+        channel_layout: ChannelLayout {
+            channels: DOLBY_CHANNELS[acmod as usize],
+            lfe: lfeon != 0,
+        },
+        samples: 1536,
+        aac_config: None,
+        dependent: false,
+    })
+}
+
+fn parse_eac3(data: &[u8]) -> Option<AudioFrameHeader> {
+    if data[0] != 0x0B || data[1] != 0x77 {
+        return None;
+    }
+
+    let strmtyp = data[2] >> 6;
+    let frmsiz = (((data[2] & 0x07) as usize) << 8) | data[3] as usize;
+
+    let fscod = data[4] >> 6;
+    let (sample_rate, blocks) = if fscod == 3 {
+        let sample_rate = match (data[4] >> 4) & 0x03 {
+            0 => 24000,
+            1 => 22050,
+            2 => 16000,
+            _ => return None,
+        };
+
+        (sample_rate, 6)
+    } else {
+        let blocks = [1, 2, 3, 6][((data[4] >> 4) & 0x03) as usize];
+        ([48000, 44100, 32000][fscod as usize], blocks)
+    };
+
+    let acmod = (data[4] >> 1) & 0x07;
+    let lfeon = data[4] & 0x01;
+
+    Some(AudioFrameHeader {
+        len: (frmsiz + 1) * 2,
+        sample_rate,
+        channel_layout: ChannelLayout {
+            channels: DOLBY_CHANNELS[acmod as usize],
+            lfe: lfeon != 0,
+        },
+        samples: 256 * blocks,
+        aac_config: None,
+        dependent: strmtyp == 1,
+    })
+}
+
+/// PTS of the frame that last took the PTS of its PES packet, and the
+/// samples decoded since.
+#[derive(Debug, Clone, Copy)]
+struct PtsAnchor {
+    pts: u64,
+    sample_rate: u32,
+    samples: u64,
+}
+
+impl PtsAnchor {
+    fn new(pts: u64, sample_rate: u32) -> Self {
+        Self {
+            pts,
+            sample_rate: sample_rate.max(1),
+            samples: 0,
+        }
+    }
+
+    fn pts(&self) -> u64 {
+        self.pts + self.samples * 90_000 / u64::from(self.sample_rate)
+    }
+}
+
+/// Splits the PES payloads of one audio PID into codec frames.
+///
+/// Frames may straddle PES packets. The PTS of a PES packet applies to the
+/// first frame that starts in it, and the following frames get PTS
+/// interpolated from the sample count. Frames before the first PTS are dropped.
+#[derive(Debug)]
+pub struct AudioSplitter {
+    codec: AudioCodec,
+    buf: BytesMut,
+
+    /// Buffer offset of the first byte of the latest PES packet and its PTS.
+    pes_pts: Option<(usize, u64)>,
+    anchor: Option<PtsAnchor>,
+}
+
+impl AudioSplitter {
+    pub fn new(codec: AudioCodec) -> Self {
+        Self {
+            codec,
+            buf: BytesMut::new(),
+            pes_pts: None,
+            anchor: None,
+        }
+    }
+
+    /// Appends the payload of a PES packet and returns every complete frame.
+    pub fn push(&mut self, pts: Option<u64>, data: &[u8], out: &mut Vec<AudioFrame>) {
+        if let Some(pts) = pts {
+            self.pes_pts = Some((self.buf.len(), pts));
+        }
+
+        self.buf.extend_from_slice(data);
+
+        while let Some(header) = self.sync() {
+            let Some(len) = self.frame_len(header) else {
+                break;
+            };
+
+            if let Some((0, pts)) = self.pes_pts {
+                self.pes_pts = None;
+                self.anchor = Some(PtsAnchor::new(pts, header.sample_rate));
+            }
+
+            let data = self.buf.split_to(len).freeze();
+            self.shift_pes_pts(len);
+
+            let Some(anchor) = &mut self.anchor else {
+                continue;
+            };
+
+            if anchor.sample_rate != header.sample_rate {
+                *anchor = PtsAnchor::new(anchor.pts(), header.sample_rate);
+            }
+
+            // counting samples rather than adding rounded durations does not drift
+            let pts = anchor.pts();
+            anchor.samples += u64::from(header.samples);
+
+            out.push(AudioFrame { pts, header, data });
+        }
+    }
+
+    /// Skips bytes up to the next frame header and returns it.
+    fn sync(&mut self) -> Option<AudioFrameHeader> {
+        let header_len = self.codec.header_len();
+
+        let mut skip = 0;
+        let found = loop {
+            if self.buf.len() - skip < header_len {
+                break None;
+            }
+
+            match self.codec.parse_header(&self.buf[skip..]) {
+                Some(header) if !header.dependent => break Some(header),
+                _ => skip += 1,
+            }
+        };
+
+        self.advance(skip);
+        found
+    }
+
+    /// Length of the frame starting the buffer, including E-AC-3 dependent
+    /// substreams, or `None` if it is not complete yet.
+    fn frame_len(&self, header: AudioFrameHeader) -> Option<usize> {
+        let mut len = header.len;
+
+        loop {
+            if self.buf.len() < len {
+                return None;
+            }
+
+            if self.codec != AudioCodec::Eac3 || self.buf.len() == len {
+                return Some(len);
+            }
+
+            if self.buf.len() < len + self.codec.header_len() {
+                return None;
+            }
+
+            match self.codec.parse_header(&self.buf[len..]) {
+                Some(next) if next.dependent => len += next.len,
+                _ => return Some(len),
+            }
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.buf.advance(n);
+        self.shift_pes_pts(n);
+    }
+
+    fn shift_pes_pts(&mut self, n: usize) {
+        if let Some((offset, _)) = &mut self.pes_pts {
+            *offset = offset.saturating_sub(n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// ADTS frame of `len` bytes, AAC LC at 48 kHz stereo.
+    fn adts(len: usize) -> Vec<u8> {
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x4C,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 0x07) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.resize(len, 0);
+        frame
+    }
+
+    /// AC-3 frame at 48 kHz, 192 kbit/s, stereo.
+    fn ac3() -> Vec<u8> {
+        let mut frame = vec![0x0B, 0x77, 0, 0, 0x14, 0x40, 0x40, 0x00];
+        frame.resize(768, 0);
+        frame
+    }
+
+    /// E-AC-3 frame at 48 kHz with 6 blocks, stereo.
+    fn eac3(dependent: bool, len: usize) -> Vec<u8> {
+        let frmsiz = len / 2 - 1;
+        let strmtyp = if dependent { 0x40 } else { 0 };
+
+        let mut frame = vec![
+            0x0B,
+            0x77,
+            strmtyp | (frmsiz >> 8) as u8,
+            frmsiz as u8,
+            0x34,
+            0x80,
+            0,
+            0,
+        ];
+        frame.resize(len, 0);
+        frame
+    }
+
+    #[test]
+    fn adts_header() {
+        let header = AudioCodec::Aac.parse_header(&adts(300)).unwrap();
+
+        assert_eq!(header.len, 300);
+        assert_eq!(header.sample_rate, 48000);
+        assert_eq!(header.channel_layout.channels, 2);
+        assert_eq!(header.samples, 1024);
+        assert_eq!(header.aac_config, Some([0x11, 0x90]));
+
+        assert_eq!(
+            AudioCodec::Aac.parse_header(&[0xFF, 0xF1, 0x4C, 0x80, 0, 0x1F, 0xFC]),
+            None
+        );
+    }
+
+    #[test]
+    fn mpeg_headers() {
+        // MPEG-1 layer II, 192 kbit/s at 48 kHz, stereo
+        let header = AudioCodec::Mpeg
+            .parse_header(&[0xFF, 0xFD, 0xA4, 0x04])
+            .unwrap();
+        assert_eq!(
+            (header.len, header.samples, header.sample_rate),
+            (576, 1152, 48000)
+        );
+        assert_eq!(header.channel_layout.channels, 2);
+
+        // MPEG-2 layer III, 64 kbit/s at 24 kHz, mono
+        let header = AudioCodec::Mpeg
+            .parse_header(&[0xFF, 0xF3, 0x84, 0xC0])
+            .unwrap();
+        assert_eq!(
+            (header.len, header.samples, header.sample_rate),
+            (192, 576, 24000)
+        );
+        assert_eq!(header.channel_layout.channels, 1);
+
+        // free format bit rate
+        assert_eq!(
+            AudioCodec::Mpeg.parse_header(&[0xFF, 0xFD, 0x04, 0x04]),
+            None
+        );
+    }
+
+    #[test]
+    fn dolby_headers() {
+        let header = AudioCodec::Ac3.parse_header(&ac3()).unwrap();
+        assert_eq!(
+            (header.len, header.samples, header.sample_rate),
+            (768, 1536, 48000)
+        );
+        assert_eq!(
+            header.channel_layout,
+            ChannelLayout {
+                channels: 2,
+                lfe: false
+            }
+        );
+
+        // 3/2 with LFE
+        let mut frame = ac3();
+        frame[6..8].copy_from_slice(&[0xE1, 0x00]);
+        let header = AudioCodec::Ac3.parse_header(&frame).unwrap();
+        assert_eq!(
+            header.channel_layout,
+            ChannelLayout {
+                channels: 5,
+                lfe: true
+            }
+        );
+
+        // the bsid tells E-AC-3 apart
+        let header = AudioCodec::Ac3.parse_header(&eac3(true, 512)).unwrap();
+        assert_eq!((header.len, header.samples), (512, 1536));
+        assert!(header.dependent);
+    }
+
+    #[test]
+    fn frames_split_across_pes_packets() {
+        let mut splitter = AudioSplitter::new(AudioCodec::Aac);
+        let mut out = Vec::new();
+
+        let data = [&b"junk"[..], &adts(200), &adts(150), &adts(100)].concat();
+        let (first, second) = data.split_at(250);
+
+        splitter.push(Some(9000), first, &mut out);
+        assert_eq!(out.len(), 1);
+
+        // the frame straddling both PES packets gets an interpolated PTS, the
+        // PTS of the second one goes to the first frame starting in it
+        splitter.push(Some(20000), second, &mut out);
+
+        let frames: Vec<_> = out.iter().map(|f| (f.pts, f.data.len())).collect();
+        assert_eq!(frames, [(9000, 200), (9000 + 1920, 150), (20000, 100)]);
+    }
+
+    #[test]
+    fn eac3_dependent_substreams_joined() {
+        let mut splitter = AudioSplitter::new(AudioCodec::Eac3);
+        let mut out = Vec::new();
+
+        let data = [eac3(false, 768), eac3(true, 256), eac3(false, 512)].concat();
+        splitter.push(Some(0), &data, &mut out);
+
+        let frames: Vec<_> = out.iter().map(|f| (f.pts, f.data.len())).collect();
+        assert_eq!(frames, [(0, 1024), (2880, 512)]);
+    }
+
+    #[test]
+    fn frames_before_first_pts_dropped() {
+        let mut splitter = AudioSplitter::new(AudioCodec::Aac);
+        let mut out = Vec::new();
+
+        splitter.push(None, &[adts(100), adts(100)].concat(), &mut out);
+        assert!(out.is_empty());
+
+        splitter.push(Some(9000), &adts(100), &mut out);
+        let frames: Vec<_> = out.iter().map(|f| f.pts).collect();
+        assert_eq!(frames, [9000]);
+    }
+
+    #[test]
+    fn interpolated_pts_does_not_drift() {
+        let mut splitter = AudioSplitter::new(AudioCodec::Aac);
+        let mut out = Vec::new();
+
+        // 44.1 kHz, 2089.79 ticks per frame
+        let mut frame = adts(100);
+        frame[2] = 0x50;
+
+        splitter.push(Some(0), &frame.repeat(101), &mut out);
+        assert_eq!(out.len(), 101);
+        assert_eq!(out[1].pts, 2089);
+        assert_eq!(out[100].pts, 100 * 1024 * 90_000 / 44_100);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use crate::mpegts::{continuity_counter::ContinuityCounter, pid::Pid, ts::TsHeader};
+
+/// Outcome of checking the continuity counter of one TS packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Continuity {
+    Ok,
+
+    /// The packet repeats the previous one and must be dropped.
+    Duplicate,
+
+    /// Packets were lost before this one.
+    Gap {
+        expected: ContinuityCounter,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PidState {
+    last: ContinuityCounter,
+    duplicated: bool,
+}
+
+/// Tracks the continuity counter of every PID.
+///
+/// Packets without payload do not increment the counter and are ignored, as
+/// are null packets. A packet may be sent twice in a row, any further
+/// repetition counts as loss.
+#[derive(Debug, Default)]
+pub struct ContinuityChecker {
+    pids: HashMap<Pid, PidState>,
+}
+
+impl ContinuityChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks a packet header. `discontinuity` is the `discontinuity_indicator`
+    /// of the packet, which allows the counter to restart at any value.
+    pub fn check(&mut self, header: &TsHeader, discontinuity: bool) -> Continuity {
+        if !header.adaptation_field_control.has_payload() || header.pid.as_u16() == Pid::NULL {
+            return Continuity::Ok;
+        }
+
+        let cc = header.continuity_counter;
+        let state = PidState {
+            last: cc,
+            duplicated: false,
+        };
+
+        let Some(prev) = self.pids.insert(header.pid, state) else {
+            return Continuity::Ok;
+        };
+
+        if discontinuity {
+            return Continuity::Ok;
+        }
+
+        let mut expected = prev.last;
+        expected.increment();
+
+        if cc == expected {
+            Continuity::Ok
+        } else if cc == prev.last && !prev.duplicated {
+            self.pids.insert(
+                header.pid,
+                PidState {
+                    last: cc,
+                    duplicated: true,
+                },
+            );
+
+            Continuity::Duplicate
+        } else {
+            Continuity::Gap { expected }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpegts::ts::{AdaptationFieldControl, TransportScramblingControl};
+
+    fn header(pid: u16, cc: u8, control: AdaptationFieldControl) -> TsHeader {
+        TsHeader {
+            transport_error_indicator: false,
+            transport_priority: false,
+            pid: Pid::new(pid).unwrap(),
+            transport_scrambling_control: TransportScramblingControl::NotScrambled,
+            continuity_counter: ContinuityCounter::from_u8(cc).unwrap(),
+            adaptation_field_control: control,
+            payload_unit_start_indicator: false,
+        }
+    }
+
+    fn check(checker: &mut ContinuityChecker, pid: u16, cc: u8) -> Continuity {
+        checker.check(&header(pid, cc, AdaptationFieldControl::PayloadOnly), false)
+    }
+
+    fn expected(cc: u8) -> Continuity {
+        Continuity::Gap {
+            expected: ContinuityCounter::from_u8(cc).unwrap(),
+        }
+    }
+
+    #[test]
+    fn counter_wraps() {
+        let mut checker = ContinuityChecker::new();
+
+        for cc in (0..16).chain(0..4) {
+            assert_eq!(check(&mut checker, 0x100, cc), Continuity::Ok);
+        }
+    }
+
+    #[test]
+    fn duplicate_dropped_once() {
+        let mut checker = ContinuityChecker::new();
+
+        assert_eq!(check(&mut checker, 0x100, 5), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x100, 5), Continuity::Duplicate);
+        assert_eq!(check(&mut checker, 0x100, 5), expected(6));
+        assert_eq!(check(&mut checker, 0x100, 6), Continuity::Ok);
+    }
+
+    #[test]
+    fn gap_reported_per_pid() {
+        let mut checker = ContinuityChecker::new();
+
+        assert_eq!(check(&mut checker, 0x100, 15), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x101, 3), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x100, 2), expected(0));
+        assert_eq!(check(&mut checker, 0x101, 4), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x100, 3), Continuity::Ok);
+    }
+
+    #[test]
+    fn discontinuity_restarts_counter() {
+        let mut checker = ContinuityChecker::new();
+        let restart = header(0x100, 9, AdaptationFieldControl::AdaptationFieldAndPayload);
+
+        assert_eq!(check(&mut checker, 0x100, 1), Continuity::Ok);
+        assert_eq!(checker.check(&restart, true), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x100, 10), Continuity::Ok);
+    }
+
+    #[test]
+    fn packets_without_payload_ignored() {
+        let mut checker = ContinuityChecker::new();
+        let pcr_only = header(0x100, 7, AdaptationFieldControl::AdaptationFieldOnly);
+
+        assert_eq!(check(&mut checker, 0x100, 1), Continuity::Ok);
+        assert_eq!(checker.check(&pcr_only, false), Continuity::Ok);
+        assert_eq!(check(&mut checker, 0x100, 2), Continuity::Ok);
+    }
+
+    #[test]
+    fn null_packets_ignored() {
+        let mut checker = ContinuityChecker::new();
+
+        assert_eq!(check(&mut checker, Pid::NULL, 0), Continuity::Ok);
+        assert_eq!(check(&mut checker, Pid::NULL, 0), Continuity::Ok);
+        assert_eq!(check(&mut checker, Pid::NULL, 9), Continuity::Ok);
+    }
+}
@@ -0,0 +1,91 @@
+use bytes::Bytes;
+use flowly::{Fourcc, FrameSource};
+
+use crate::frame::Mpeg2TsDemuxFrame;
+
+/// An entry of a program association table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramInfo {
+    pub program_num: u16,
+
+    /// PID carrying the program map table, or the network information table
+    /// for program number 0.
+    pub pmt_pid: u16,
+}
+
+/// An elementary stream announced by a program map table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub pid: u16,
+    pub stream_type: u8,
+
+    /// Codec of the stream if the demuxer can emit its frames.
+    pub codec: Option<Fourcc>,
+}
+
+/// Everything the demuxer reports when events are enabled, see
+/// [`Mpeg2TsDemux::parse_event`](crate::Mpeg2TsDemux::parse_event).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mpeg2TsDemuxEvent<S: FrameSource> {
+    Frame(Mpeg2TsDemuxFrame<S>),
+
+    /// A new or changed program association table.
+    PatUpdated {
+        transport_stream_id: u16,
+        version: u8,
+        programs: Vec<ProgramInfo>,
+    },
+
+    /// A new or changed program map table.
+    PmtUpdated {
+        program_num: u16,
+        version: u8,
+        pcr_pid: Option<u16>,
+        streams: Vec<StreamInfo>,
+    },
+
+    /// Packets were lost on `pid`: `actual` was received where `expected` was due.
+    ContinuityError {
+        pid: u16,
+        expected: u8,
+        actual: u8,
+    },
+
+    /// The PCR on `pid` jumped, either signalled by `discontinuity_indicator`
+    /// or by more than a second. Values are unwrapped PCRs in 27 MHz units.
+    PcrDiscontinuity {
+        pid: u16,
+        previous: Option<u64>,
+        current: u64,
+    },
+
+    /// Number of packets on `pid` left until a splicing point.
+    SpliceCountdown {
+        pid: u16,
+        countdown: i8,
+    },
+
+    /// A new or changed PSI table other than the PAT and PMTs.
+    ///
+    /// Short-form sections such as SCTE-35 `splice_info_section` have no
+    /// version and are reported every time, with `table_id_extension` and
+    /// `version` set to 0 and their body as the only `data` entry.
+    Section {
+        pid: u16,
+        table_id: u8,
+        table_id_extension: u16,
+        version: u8,
+
+        /// `table_data` of every section, ordered by `section_number`.
+        data: Vec<Bytes>,
+    },
+
+    /// `skipped` bytes that did not belong to a packet were dropped to find
+    /// the packet boundaries.
+    SyncLost {
+        skipped: usize,
+    },
+
+    /// The first packet on a PID that no PAT or PMT announced.
+    UnknownPid(u16),
+}
@@ -0,0 +1,512 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly::Fourcc;
+
+/// How a NAL unit relates to access unit boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalClass {
+    /// A slice, `first` if it starts a picture.
+    Vcl {
+        first: bool,
+    },
+
+    /// A NAL unit that starts a new access unit when it follows a slice.
+    Prefix,
+    Other,
+}
+
+/// Video codecs whose elementary streams are made of Annex B NAL units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalCodec {
+    H264,
+    H265,
+}
+
+impl NalCodec {
+    pub fn fourcc(self) -> Fourcc {
+        match self {
+            NalCodec::H264 => Fourcc::VIDEO_AVC,
+            NalCodec::H265 => Fourcc::VIDEO_HEVC,
+        }
+    }
+
+    /// Returns the type of a NAL unit.
+    pub fn nal_type(self, nal: &[u8]) -> Option<u8> {
+        let &first = nal.first()?;
+
+        Some(match self {
+            NalCodec::H264 => first & 0x1F,
+            NalCodec::H265 => (first >> 1) & 0x3F,
+        })
+    }
+
+    /// Classifies a NAL unit by its header and first payload byte.
+    pub fn classify(self, nal: &[u8]) -> NalClass {
+        let Some(nal_type) = self.nal_type(nal) else {
+            return NalClass::Other;
+        };
+
+        match (self, nal_type) {
+            // first_mb_in_slice is 0 if its ue(v) code starts with 1
+            (NalCodec::H264, 1..=5) => NalClass::Vcl {
+                first: nal.get(1).is_some_and(|b| b & 0x80 != 0),
+            },
+
+            // SEI, SPS, PPS, AUD and reserved types
+            (NalCodec::H264, 6..=9 | 14..=18) => NalClass::Prefix,
+
+            // first_slice_segment_in_pic_flag
+            (NalCodec::H265, 0..=31) => NalClass::Vcl {
+                first: nal.get(2).is_some_and(|b| b & 0x80 != 0),
+            },
+
+            // VPS, SPS, PPS, AUD, prefix SEI and reserved types
+            (NalCodec::H265, 32..=35 | 39 | 41..=44 | 48..=55) => NalClass::Prefix,
+            _ => NalClass::Other,
+        }
+    }
+
+    /// Returns `true` for the VPS, SPS and PPS NAL unit types.
+    pub fn is_parameter_set(self, nal_type: u8) -> bool {
+        match self {
+            NalCodec::H264 => matches!(nal_type, 7 | 8),
+            NalCodec::H265 => matches!(nal_type, 32..=34),
+        }
+    }
+
+    /// Reads the id of a parameter set: `vps_video_parameter_set_id`,
+    /// `seq_parameter_set_id` or `pic_parameter_set_id`.
+    pub fn parameter_set_id(self, nal_type: u8, nal: &[u8]) -> Option<u32> {
+        let header_len = match self {
+            NalCodec::H264 => 1,
+            NalCodec::H265 => 2,
+        };
+
+        let rbsp = unescape(nal.get(header_len..)?);
+        let mut reader = BitReader::new(&rbsp);
+
+        match (self, nal_type) {
+            // profile_idc, constraint flags and level_idc come first
+            (NalCodec::H264, 7) => {
+                reader.skip(24)?;
+                reader.read_ue()
+            }
+
+            (NalCodec::H264, _) | (NalCodec::H265, 34) => reader.read_ue(),
+            (NalCodec::H265, 32) => reader.read_bits(4),
+
+            (NalCodec::H265, _) => {
+                reader.skip(4)?;
+                let max_sub_layers_minus1 = reader.read_bits(3)? as usize;
+                reader.skip(1)?;
+
+                // profile_tier_level: general profile and level, sub-layer
+                // presence flags padded to 8 entries, then the sub-layers
+                reader.skip(96)?;
+
+                let mut sub_layers = Vec::with_capacity(max_sub_layers_minus1);
+                for _ in 0..max_sub_layers_minus1 {
+                    sub_layers.push((reader.read_bits(1)? != 0, reader.read_bits(1)? != 0));
+                }
+
+                if max_sub_layers_minus1 > 0 {
+                    reader.skip(2 * (8 - max_sub_layers_minus1))?;
+                }
+
+                for (profile_present, level_present) in sub_layers {
+                    if profile_present {
+                        reader.skip(88)?;
+                    }
+
+                    if level_present {
+                        reader.skip(8)?;
+                    }
+                }
+
+                reader.read_ue()
+            }
+        }
+    }
+
+    /// Returns `true` if the access unit in `data` can be decoded on its own: an
+    /// H.264 IDR picture or recovery point, or an H.265 IRAP picture.
+    pub fn is_keyframe(self, data: &[u8]) -> bool {
+        nal_units(data).any(|nal| match (self, self.nal_type(nal)) {
+            // IDR slice
+            (NalCodec::H264, Some(5)) => true,
+            (NalCodec::H264, Some(6)) => has_recovery_point(&unescape(&nal[1..])),
+            // BLA, IDR, CRA and reserved IRAP types
+            (NalCodec::H265, Some(16..=23)) => true,
+            _ => false,
+        })
+    }
+
+    /// Returns `true` if `data` ends with a complete access unit delimiter,
+    /// end-of-sequence or end-of-stream NAL unit following a slice. A leading
+    /// delimiter alone does not end the access unit it starts.
+    pub fn ends_access_unit(self, data: &[u8]) -> bool {
+        let tail_start = data.len().saturating_sub(8);
+        let Some(pos) = data[tail_start..].windows(3).rposition(|w| w == [0, 0, 1]) else {
+            return false;
+        };
+
+        let start = tail_start + pos;
+        let nal = &data[start + 3..];
+        let Some(&first) = nal.first() else {
+            return false;
+        };
+
+        let ends = match self {
+            NalCodec::H264 => match first & 0x1F {
+                // access unit delimiter
+                9 => nal.len() == 2,
+                // end of sequence, end of stream
+                10 | 11 => nal.len() == 1,
+                _ => false,
+            },
+
+            NalCodec::H265 => match (first >> 1) & 0x3F {
+                35 => nal.len() == 3,
+                36 | 37 => nal.len() == 2,
+                _ => false,
+            },
+        };
+
+        ends && nal_units(&data[..start])
+            .any(|nal| matches!(self.classify(nal), NalClass::Vcl { .. }))
+    }
+}
+
+/// Iterates over the NAL units of Annex B data, without their start codes.
+pub fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = match find_start_code(data) {
+        Some(pos) => &data[pos + 3..],
+        None => &[][..],
+    };
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (nal, next) = match find_start_code(rest) {
+            Some(pos) => (&rest[..pos], &rest[pos + 3..]),
+            None => (rest, &[][..]),
+        };
+
+        rest = next;
+
+        // zero bytes before a start code are trailing_zero_8bits or part of a 4-byte start code
+        let len = nal.iter().rposition(|&b| b != 0).map_or(0, |pos| pos + 1);
+        Some(&nal[..len])
+    })
+    .filter(|nal| !nal.is_empty())
+}
+
+/// Removes the emulation prevention bytes of a NAL unit payload.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &b in data {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+
+    out
+}
+
+/// Returns `true` if an H.264 SEI payload holds a recovery point message.
+fn has_recovery_point(mut sei: &[u8]) -> bool {
+    const RECOVERY_POINT: usize = 6;
+
+    // stop at rbsp_trailing_bits
+    while sei.len() > 1 {
+        let Some(payload_type) = read_sei_value(&mut sei) else {
+            return false;
+        };
+
+        if payload_type == RECOVERY_POINT {
+            return true;
+        }
+
+        let Some(size) = read_sei_value(&mut sei) else {
+            return false;
+        };
+
+        sei = sei.get(size..).unwrap_or_default();
+    }
+
+    false
+}
+
+/// Reads an SEI payload type or size, coded as a run of 0xFF bytes and a last byte.
+fn read_sei_value(data: &mut &[u8]) -> Option<usize> {
+    let mut value = 0;
+
+    loop {
+        let (&b, rest) = data.split_first()?;
+        *data = rest;
+        value += b as usize;
+
+        if b != 0xFF {
+            return Some(value);
+        }
+    }
+}
+
+/// Reads the bits of an RBSP, most significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        if self.pos + n > self.data.len() * 8 {
+            return None;
+        }
+
+        self.pos += n;
+        Some(())
+    }
+
+    fn read_bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+
+        for _ in 0..n {
+            let &byte = self.data.get(self.pos / 8)?;
+            value = (value << 1) | u32::from((byte >> (7 - self.pos % 8)) & 1);
+            self.pos += 1;
+        }
+
+        Some(value)
+    }
+
+    /// Reads an unsigned Exp-Golomb code.
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bits(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+
+        Some((1 << zeros) - 1 + self.read_bits(zeros)?)
+    }
+}
+
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0, 0, 1])
+}
+
+/// The latest parameter sets of a video stream, in Annex B format.
+#[derive(Debug)]
+pub struct ParamSets {
+    codec: NalCodec,
+
+    /// NAL unit type and parameter set id, and the parameter set with its
+    /// start code, ordered by type and id.
+    sets: Vec<((u8, u32), Bytes)>,
+}
+
+impl ParamSets {
+    pub fn new(codec: NalCodec) -> Self {
+        Self {
+            codec,
+            sets: Vec::new(),
+        }
+    }
+
+    /// Scans an access unit for parameter sets.
+    ///
+    /// Returns whether the access unit carries any, and whether they differ
+    /// from the ones already known.
+    pub fn update(&mut self, data: &[u8]) -> (bool, bool) {
+        let mut found = false;
+        let mut changed = false;
+
+        for nal in nal_units(data) {
+            let Some(nal_type) = self.codec.nal_type(nal) else {
+                continue;
+            };
+
+            if !self.codec.is_parameter_set(nal_type) {
+                continue;
+            }
+
+            found = true;
+
+            // unreadable ids share the slot of id 0
+            let id = self.codec.parameter_set_id(nal_type, nal).unwrap_or(0);
+            match self
+                .sets
+                .binary_search_by_key(&(nal_type, id), |(key, _)| *key)
+            {
+                Ok(idx) if &self.sets[idx].1[4..] == nal => {}
+                Ok(idx) => {
+                    self.sets[idx].1 = with_start_code(nal);
+                    changed = true;
+                }
+                Err(idx) => {
+                    self.sets
+                        .insert(idx, ((nal_type, id), with_start_code(nal)));
+                    changed = true;
+                }
+            }
+        }
+
+        (found, changed)
+    }
+
+    pub fn to_vec(&self) -> Vec<Bytes> {
+        self.sets.iter().map(|(_, set)| set.clone()).collect()
+    }
+}
+
+fn with_start_code(nal: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(4 + nal.len());
+    buf.put_slice(&[0, 0, 0, 1]);
+    buf.put_slice(nal);
+    buf.freeze()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1F];
+    const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84];
+    const SLICE: &[u8] = &[0x41, 0x9A, 0x02];
+
+    /// Joins NAL units into Annex B data, with 4-byte start codes.
+    fn annex_b(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+            .collect()
+    }
+
+    fn with_start_codes(nals: &[&[u8]]) -> Vec<Bytes> {
+        nals.iter().map(|nal| with_start_code(nal)).collect()
+    }
+
+    #[test]
+    fn h264_param_sets_kept() {
+        let mut params = ParamSets::new(NalCodec::H264);
+
+        assert_eq!(params.update(&annex_b(&[PPS, SPS, IDR])), (true, true));
+        assert_eq!(params.to_vec(), with_start_codes(&[SPS, PPS]));
+
+        // repeated sets are not a change
+        assert_eq!(params.update(&annex_b(&[SPS, PPS, IDR])), (true, false));
+        assert_eq!(params.update(&annex_b(&[SLICE])), (false, false));
+
+        let pps = &[0x68, 0xCE, 0x38, 0x80];
+        assert_eq!(params.update(&annex_b(&[pps, IDR])), (true, true));
+        assert_eq!(params.to_vec(), with_start_codes(&[SPS, pps]));
+    }
+
+    #[test]
+    fn h265_param_sets_kept() {
+        let vps = &[0x40, 0x01, 0x0C];
+        let sps = &[0x42, 0x01, 0x01];
+        let pps = &[0x44, 0x01, 0xC1];
+        let idr = &[0x26, 0x01, 0xAF];
+
+        // 3-byte start codes and trailing zero bytes
+        let mut data = vec![0, 0, 1];
+        data.extend_from_slice(&[&pps[..], &[0, 0, 0, 1], sps, &[0, 0, 1], vps, &[0, 0]].concat());
+        data.extend_from_slice(&annex_b(&[idr]));
+
+        let mut params = ParamSets::new(NalCodec::H265);
+        assert_eq!(params.update(&data), (true, true));
+        assert_eq!(params.to_vec(), with_start_codes(&[vps, sps, pps]));
+    }
+
+    #[test]
+    fn h264_keyframes() {
+        let codec = NalCodec::H264;
+
+        assert!(codec.is_keyframe(&annex_b(&[SPS, PPS, IDR])));
+        assert!(!codec.is_keyframe(&annex_b(&[SLICE])));
+
+        // recovery point SEI after a user data one
+        let sei = &[0x06, 0x05, 0x01, 0xAA, 0x06, 0x01, 0x84, 0x80];
+        assert!(codec.is_keyframe(&annex_b(&[sei, SLICE])));
+
+        // buffering period SEI only
+        let sei = &[0x06, 0x00, 0x01, 0xAA, 0x80];
+        assert!(!codec.is_keyframe(&annex_b(&[sei, SLICE])));
+
+        // the payload size counts the unescaped bytes
+        let sei = &[
+            0x06, 0x05, 0x03, 0x00, 0x00, 0x03, 0x01, 0x06, 0x01, 0x84, 0x80,
+        ];
+        assert!(codec.is_keyframe(&annex_b(&[sei, SLICE])));
+    }
+
+    #[test]
+    fn h265_keyframes() {
+        let codec = NalCodec::H265;
+
+        // IDR_W_RADL, CRA_NUT
+        assert!(codec.is_keyframe(&annex_b(&[&[0x26, 0x01, 0xAF]])));
+        assert!(codec.is_keyframe(&annex_b(&[&[0x2A, 0x01, 0xAF]])));
+
+        // TRAIL_R, after a prefix SEI
+        let trail = &[0x02, 0x01, 0xD0];
+        assert!(!codec.is_keyframe(&annex_b(&[&[0x4E, 0x01, 0x05], trail])));
+    }
+
+    #[test]
+    fn param_sets_keyed_by_id() {
+        let pps1 = &[0x68, 0x5B, 0x3C, 0x80];
+        let mut params = ParamSets::new(NalCodec::H264);
+
+        assert_eq!(NalCodec::H264.parameter_set_id(8, pps1), Some(1));
+        assert_eq!(NalCodec::H264.parameter_set_id(8, PPS), Some(0));
+
+        assert_eq!(
+            params.update(&annex_b(&[SPS, PPS, pps1, IDR])),
+            (true, true)
+        );
+        assert_eq!(params.to_vec(), with_start_codes(&[SPS, PPS, pps1]));
+
+        // alternating ids are not a change
+        for pps in [PPS, pps1, PPS, pps1] {
+            assert_eq!(params.update(&annex_b(&[pps, SLICE])), (true, false));
+        }
+
+        assert_eq!(params.to_vec(), with_start_codes(&[SPS, PPS, pps1]));
+    }
+
+    #[test]
+    fn h265_parameter_set_ids() {
+        let codec = NalCodec::H265;
+
+        // vps_video_parameter_set_id 3
+        assert_eq!(
+            codec.parameter_set_id(32, &[0x40, 0x01, 0x3C, 0x01]),
+            Some(3)
+        );
+
+        // pps_pic_parameter_set_id 2
+        assert_eq!(codec.parameter_set_id(34, &[0x44, 0x01, 0x68]), Some(2));
+
+        // sps_max_sub_layers_minus1 1 without sub-layer profile or level, sps_seq_parameter_set_id 1
+        let mut sps = vec![0x42, 0x01, 0x03];
+        sps.extend_from_slice(&[0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 0x5D]);
+        sps.extend_from_slice(&[0x00, 0x00, 0x40]);
+        assert_eq!(codec.parameter_set_id(33, &sps), Some(1));
+    }
+}
@@ -0,0 +1,329 @@
+use bytes::{Buf, BytesMut};
+
+use crate::{format::PacketFormat, mpegts::ts::TsPacket};
+
+/// Result of aligning the input on a packet boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    /// Bytes dropped in front of the packet boundary.
+    pub skipped: usize,
+
+    /// `true` if a whole packet starts at the beginning of the input.
+    pub ready: bool,
+}
+
+/// Outcome of checking one framing at one position.
+enum Candidate {
+    Confirmed,
+    Rejected,
+    Undecided,
+}
+
+/// Finds packet boundaries in a byte stream.
+///
+/// Sync is acquired once `TsPacket::SYNC_BYTE` is found at the start of
+/// `confirm` consecutive packets, or of the packets left at the end of the
+/// input, and is lost as soon as a packet does not start with it. A packet
+/// cut short, with the next one starting within its span, is dropped. Unless
+/// fixed, the packet format is detected along with the sync.
+#[derive(Debug)]
+pub struct PacketSync {
+    confirm: usize,
+    fixed: Option<PacketFormat>,
+    locked: Option<PacketFormat>,
+    end_of_input: bool,
+}
+
+impl PacketSync {
+    pub fn new(confirm: usize, format: Option<PacketFormat>) -> Self {
+        Self {
+            confirm: confirm.max(1),
+            fixed: format,
+            locked: None,
+            end_of_input: false,
+        }
+    }
+
+    /// Marks the end of the input, after which fewer than `confirm` packets
+    /// are enough to acquire sync.
+    pub fn end_of_input(&mut self) {
+        self.end_of_input = true;
+    }
+
+    /// Returns the format of the packets while in sync.
+    pub fn format(&self) -> Option<PacketFormat> {
+        self.locked
+    }
+
+    /// Drops the bytes in front of the next packet boundary of `src`.
+    ///
+    /// Bytes that may still start a packet are kept until enough data arrived
+    /// to confirm or reject them.
+    pub fn align(&mut self, src: &mut BytesMut) -> Alignment {
+        if let Some(format) = self.locked {
+            if src.len() < format.size() {
+                return Alignment {
+                    skipped: 0,
+                    ready: false,
+                };
+            }
+
+            let ready = if src[format.ts_offset()] != TsPacket::SYNC_BYTE {
+                false
+            } else {
+                match src.get(format.size() + format.ts_offset()) {
+                    Some(&TsPacket::SYNC_BYTE) => true,
+                    None if self.end_of_input => true,
+                    None => {
+                        return Alignment {
+                            skipped: 0,
+                            ready: false,
+                        };
+                    }
+
+                    // garbage after the packet, or a packet cut short
+                    Some(_) => match self.check_truncated(src, format) {
+                        Candidate::Rejected => true,
+                        Candidate::Confirmed => false,
+                        Candidate::Undecided => {
+                            return Alignment {
+                                skipped: 0,
+                                ready: false,
+                            };
+                        }
+                    },
+                }
+            };
+
+            if ready {
+                return Alignment {
+                    skipped: 0,
+                    ready: true,
+                };
+            }
+
+            self.locked = None;
+        }
+
+        self.hunt(src)
+    }
+
+    fn hunt(&mut self, src: &mut BytesMut) -> Alignment {
+        let fixed = self.fixed.map(|format| [format]);
+        let formats = fixed.as_ref().map_or(&PacketFormat::ALL[..], |x| &x[..]);
+
+        for start in 0..src.len() {
+            let mut undecided = false;
+
+            for &format in formats {
+                match self.check(src, start, format) {
+                    Candidate::Confirmed if !undecided => {
+                        src.advance(start);
+                        self.locked = Some(format);
+
+                        return Alignment {
+                            skipped: start,
+                            ready: src.len() >= format.size(),
+                        };
+                    }
+
+                    Candidate::Confirmed | Candidate::Undecided => undecided = true,
+                    Candidate::Rejected => {}
+                }
+            }
+
+            // wait for more data before ruling out a preferred format
+            if undecided {
+                src.advance(start);
+
+                return Alignment {
+                    skipped: start,
+                    ready: false,
+                };
+            }
+        }
+
+        let skipped = src.len();
+        src.clear();
+
+        Alignment {
+            skipped,
+            ready: false,
+        }
+    }
+
+    /// Checks whether a packet starts within the span of the one at the start
+    /// of `src`, followed by another packet.
+    fn check_truncated(&self, src: &[u8], format: PacketFormat) -> Candidate {
+        let mut undecided = false;
+
+        for start in 1..format.size() {
+            let pos = start + format.ts_offset();
+            if src[pos] != TsPacket::SYNC_BYTE {
+                continue;
+            }
+
+            match src.get(pos + format.size()) {
+                Some(&TsPacket::SYNC_BYTE) => return Candidate::Confirmed,
+                Some(_) => {}
+                None => undecided = true,
+            }
+        }
+
+        if undecided && !self.end_of_input {
+            Candidate::Undecided
+        } else {
+            Candidate::Rejected
+        }
+    }
+
+    fn check(&self, src: &[u8], start: usize, format: PacketFormat) -> Candidate {
+        for i in 0..self.confirm {
+            match src.get(start + format.ts_offset() + i * format.size()) {
+                Some(&TsPacket::SYNC_BYTE) => {}
+                Some(_) => return Candidate::Rejected,
+                None if !self.end_of_input => return Candidate::Undecided,
+                None if i > 0 => return Candidate::Confirmed,
+                None => return Candidate::Rejected,
+            }
+        }
+
+        Candidate::Confirmed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stream(garbage: usize, packets: usize) -> BytesMut {
+        framed(PacketFormat::Ts, garbage, packets)
+    }
+
+    /// Null packets in `format` framing, with zeroed extra bytes.
+    fn framed(format: PacketFormat, garbage: usize, packets: usize) -> BytesMut {
+        let mut packet = vec![0; format.size()];
+        let ts = &mut packet[format.ts_offset()..][..TsPacket::SIZE];
+        ts.fill(0xFF);
+        ts[..4].copy_from_slice(&[TsPacket::SYNC_BYTE, 0x1F, 0xFF, 0x10]);
+
+        let mut src = BytesMut::zeroed(garbage);
+        for _ in 0..packets {
+            src.extend_from_slice(&packet);
+        }
+
+        src
+    }
+
+    /// Takes every packet `sync` finds in `src`, returning their count.
+    fn packets(sync: &mut PacketSync, src: &mut BytesMut) -> usize {
+        let mut count = 0;
+
+        while sync.align(src).ready {
+            src.advance(sync.format().unwrap().size());
+            count += 1;
+        }
+
+        count
+    }
+
+    #[test]
+    fn garbage_prefix_skipped() {
+        let mut sync = PacketSync::new(3, None);
+        let mut src = stream(50, 4);
+
+        let alignment = sync.align(&mut src);
+        assert_eq!(alignment.skipped, 50);
+        assert!(alignment.ready);
+        assert_eq!(sync.format(), Some(PacketFormat::Ts));
+
+        // the last packet waits for the next boundary or the end of the input
+        assert_eq!(packets(&mut sync, &mut src), 3);
+        sync.end_of_input();
+        assert_eq!(packets(&mut sync, &mut src), 1);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn short_input_parsed_at_end_of_input() {
+        let mut sync = PacketSync::new(3, None);
+        let mut src = stream(10, 2);
+
+        assert_eq!(packets(&mut sync, &mut src), 0);
+        assert_eq!(src.len(), 2 * TsPacket::SIZE);
+
+        sync.end_of_input();
+        assert_eq!(packets(&mut sync, &mut src), 2);
+        assert!(src.is_empty());
+
+        // a single packet is enough too
+        let mut sync = PacketSync::new(3, Some(PacketFormat::Ts));
+        let mut src = stream(0, 1);
+        sync.end_of_input();
+        assert_eq!(packets(&mut sync, &mut src), 1);
+    }
+
+    #[test]
+    fn packet_before_garbage_kept() {
+        let mut sync = PacketSync::new(3, None);
+        let mut src = stream(0, 4);
+        src.extend_from_slice(&[0; 100]);
+
+        // the last packet is not dropped for the garbage after it
+        assert_eq!(packets(&mut sync, &mut src), 4);
+        assert_eq!(src.len(), 100);
+    }
+
+    #[test]
+    fn truncated_packet_dropped() {
+        let mut sync = PacketSync::new(3, None);
+        let mut src = stream(0, 3);
+        src.extend_from_slice(&stream(0, 1)[..100]);
+        src.extend_from_slice(&stream(0, 3));
+
+        sync.end_of_input();
+
+        let mut count = 0;
+        let mut skipped = 0;
+        loop {
+            let alignment = sync.align(&mut src);
+            skipped += alignment.skipped;
+            if !alignment.ready {
+                break;
+            }
+
+            let packet = src.split_to(TsPacket::SIZE);
+            assert_eq!(packet, stream(0, 1));
+            count += 1;
+        }
+
+        // the good packet after the cut is kept whole
+        assert_eq!((count, skipped), (6, 100));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn m2ts_and_rs_detected() {
+        for format in [PacketFormat::M2ts, PacketFormat::Rs] {
+            let mut sync = PacketSync::new(3, None);
+            let mut src = framed(format, 20, 4);
+
+            assert_eq!(sync.align(&mut src).skipped, 20);
+            assert_eq!(sync.format(), Some(format));
+
+            sync.end_of_input();
+            assert_eq!(packets(&mut sync, &mut src), 4);
+            assert!(src.is_empty());
+        }
+    }
+
+    #[test]
+    fn fixed_format_not_detected() {
+        let mut sync = PacketSync::new(3, Some(PacketFormat::Ts));
+        let mut src = framed(PacketFormat::M2ts, 0, 4);
+
+        // 192-byte packets never line up as 188-byte ones
+        assert_eq!(packets(&mut sync, &mut src), 0);
+        assert_eq!(sync.format(), None);
+    }
+}
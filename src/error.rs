@@ -2,9 +2,6 @@ use flowly::{Fourcc, Void};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error<E = Void> {
-    #[error("Mpeg2Ts Error: {0}")]
-    TsError(#[from] mpeg2ts::Error),
-
     #[error("Not an audio ID: {0}")]
     WrongAudioStreamId(u8),
 
@@ -20,6 +17,30 @@ pub enum Error<E = Void> {
     #[error("Wrong SyncByte")]
     WrogSyncByte,
 
+    #[error("Not enough data: required {required} bytes, remaining {remaining}")]
+    NotEnoughData { required: usize, remaining: usize },
+
+    #[error("Bad packet start code prefix: {0:#08x}")]
+    BadStartCode(u64),
+
+    #[error("CRC32 mismatch: expected {expected:#010x}, actual {actual:#010x}")]
+    CrcMismatch { expected: u32, actual: u32 },
+
+    #[error("Unexpected PTS/DTS prefix: {0:#06b}")]
+    UnexpectedTimestampPrefix(u8),
+
+    #[error("Unsupported PES flag: {0}")]
+    UnsupportedPesFlag(&'static str),
+
+    #[error("Unexpected Reserved Bits: {0:#x}")]
+    UnexpectedReservedBits(u64),
+
+    #[error("Pointer field points past the packet payload: {0}")]
+    InvalidPointerField(u8),
+
+    #[error("PSI table has no syntax section")]
+    MissingTableSyntax,
+
     #[error("Unknown PID: {0}")]
     UnknownPid(u16),
 
@@ -29,6 +50,9 @@ pub enum Error<E = Void> {
     #[error("Unsupported Codec {0}")]
     MuxUnsupportedCodec(Fourcc),
 
+    #[error("Codec changed to {0} within the muxed stream")]
+    MuxCodecChanged(Fourcc),
+
     #[error(transparent)]
     Other(E),
 }
@@ -36,15 +60,29 @@ pub enum Error<E = Void> {
 impl Error {
     pub fn extend<E>(self) -> Error<E> {
         match self {
-            Error::TsError(error) => Error::TsError(error),
             Error::WrongAudioStreamId(id) => Error::WrongAudioStreamId(id),
             Error::WrongVideoStreamId(id) => Error::WrongVideoStreamId(id),
             Error::ValueTooLarge(val) => Error::ValueTooLarge(val),
             Error::UnexpectedMarkerBit(mask) => Error::UnexpectedMarkerBit(mask),
             Error::WrogSyncByte => Error::WrogSyncByte,
+            Error::NotEnoughData {
+                required,
+                remaining,
+            } => Error::NotEnoughData {
+                required,
+                remaining,
+            },
+            Error::BadStartCode(code) => Error::BadStartCode(code),
+            Error::CrcMismatch { expected, actual } => Error::CrcMismatch { expected, actual },
+            Error::UnexpectedTimestampPrefix(prefix) => Error::UnexpectedTimestampPrefix(prefix),
+            Error::UnsupportedPesFlag(flag) => Error::UnsupportedPesFlag(flag),
+            Error::UnexpectedReservedBits(bits) => Error::UnexpectedReservedBits(bits),
+            Error::InvalidPointerField(n) => Error::InvalidPointerField(n),
+            Error::MissingTableSyntax => Error::MissingTableSyntax,
             Error::UnknownPid(pid) => Error::UnknownPid(pid),
             Error::PsiTableCountZero => Error::PsiTableCountZero,
             Error::MuxUnsupportedCodec(fourcc) => Error::MuxUnsupportedCodec(fourcc),
+            Error::MuxCodecChanged(fourcc) => Error::MuxCodecChanged(fourcc),
             Error::Other(_) => unreachable!(),
         }
     }
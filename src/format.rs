@@ -0,0 +1,39 @@
+use crate::mpegts::ts::TsPacket;
+
+/// Framing of the TS packets in a byte stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketFormat {
+    /// Plain 188-byte packets.
+    #[default]
+    Ts,
+
+    /// 192-byte packets of Blu-ray/AVCHD `.m2ts` files: a 4-byte
+    /// TP_extra_header carrying the arrival time stamp, then the TS packet.
+    M2ts,
+
+    /// 204-byte packets: the TS packet followed by 16 bytes of Reed-Solomon parity.
+    Rs,
+}
+
+impl PacketFormat {
+    /// Formats tried in turn when detecting the framing of a stream.
+    pub(crate) const ALL: [PacketFormat; 3] =
+        [PacketFormat::Ts, PacketFormat::M2ts, PacketFormat::Rs];
+
+    /// Size of a framed packet in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            PacketFormat::Ts => TsPacket::SIZE,
+            PacketFormat::M2ts => TsPacket::SIZE + 4,
+            PacketFormat::Rs => TsPacket::SIZE + 16,
+        }
+    }
+
+    /// Offset of the TS packet within a framed packet.
+    pub fn ts_offset(self) -> usize {
+        match self {
+            PacketFormat::M2ts => 4,
+            PacketFormat::Ts | PacketFormat::Rs => 0,
+        }
+    }
+}
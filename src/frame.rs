@@ -6,10 +6,30 @@ use flowly::{DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource};
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Mpeg2TsSource<S: FrameSource> {
     pub codec: Fourcc,
+
+    /// Latest VPS, SPS and PPS of a video stream, each with an Annex B start code.
     pub params: Vec<Bytes>,
+
+    /// Number of the program the elementary stream belongs to.
+    pub program_num: u16,
+
+    /// PID carrying the elementary stream.
+    pub pid: u16,
     inner: S,
 }
 
+impl<S: FrameSource> Mpeg2TsSource<S> {
+    pub(crate) fn new(codec: Fourcc, program_num: u16, pid: u16, inner: S) -> Self {
+        Self {
+            codec,
+            params: Vec::new(),
+            program_num,
+            pid,
+            inner,
+        }
+    }
+}
+
 impl<S: FrameSource> FrameSource for Mpeg2TsSource<S> {
     type Source = S;
 
@@ -20,13 +40,51 @@ impl<S: FrameSource> FrameSource for Mpeg2TsSource<S> {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Mpeg2TsFrame<S: FrameSource> {
+    /// Presentation time in microseconds.
     pub pts: i64,
+
+    /// Decode time in microseconds, the PTS if the PES packet carries no DTS.
     pub dts: u64,
     pub keyframe: bool,
+
+    /// The payload carries parameter sets in-band.
+    pub has_params: bool,
+
+    /// Arrival time stamp of the first packet of the frame in 27 MHz units,
+    /// for M2TS input.
+    pub arrival_time: Option<u32>,
+
+    /// Packets carrying the frame were lost, the payload is incomplete.
+    pub corrupt: bool,
     pub payload: Bytes,
     source: Arc<Mpeg2TsSource<S>>,
 }
 
+impl<S: FrameSource> Mpeg2TsFrame<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        pts: i64,
+        dts: u64,
+        keyframe: bool,
+        has_params: bool,
+        arrival_time: Option<u32>,
+        corrupt: bool,
+        payload: Bytes,
+        source: Arc<Mpeg2TsSource<S>>,
+    ) -> Self {
+        Self {
+            pts,
+            dts,
+            keyframe,
+            has_params,
+            arrival_time,
+            corrupt,
+            payload,
+            source,
+        }
+    }
+}
+
 impl<S: FrameSource> EncodedFrame for Mpeg2TsFrame<S> {
     type Param = Bytes;
 
@@ -66,11 +124,220 @@ impl<S: FrameSource> Frame for Mpeg2TsFrame<S> {
     }
 
     fn flags(&self) -> FrameFlags {
-        self.keyframe
-            .then_some(FrameFlags::KEYFRAME)
-            .unwrap_or_default()
-            | FrameFlags::ENCODED
-            | FrameFlags::ANNEXB
-            | FrameFlags::VIDEO_STREAM
+        let mut flags = FrameFlags::ENCODED | FrameFlags::ANNEXB | FrameFlags::VIDEO_STREAM;
+
+        if self.keyframe {
+            flags |= FrameFlags::KEYFRAME;
+        }
+
+        if self.has_params {
+            flags |= FrameFlags::HAS_PARAMS;
+        }
+
+        flags
+    }
+}
+
+/// Speaker configuration of an audio stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelLayout {
+    /// Number of full-range channels.
+    pub channels: u8,
+
+    /// `true` if a low-frequency effects channel is present.
+    pub lfe: bool,
+}
+
+impl ChannelLayout {
+    /// Total number of channels, LFE included.
+    pub fn count(&self) -> u8 {
+        self.channels + self.lfe as u8
+    }
+}
+
+/// A single audio frame (AAC, MPEG audio, AC-3 or E-AC-3) split out of a PES packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mpeg2TsAudioFrame<S: FrameSource> {
+    pub pts: i64,
+    pub dts: u64,
+    pub sample_rate: u32,
+    pub channel_layout: ChannelLayout,
+    pub samples: u32,
+
+    /// Decoder configuration, e.g. the AudioSpecificConfig of an AAC stream.
+    pub config: Option<Bytes>,
+
+    /// Arrival time stamp of the first packet of the PES packet carrying the
+    /// frame in 27 MHz units, for M2TS input.
+    pub arrival_time: Option<u32>,
+
+    /// Packets of the PES packet carrying the frame were lost.
+    pub corrupt: bool,
+    pub payload: Bytes,
+    source: Arc<Mpeg2TsSource<S>>,
+}
+
+impl<S: FrameSource> Mpeg2TsAudioFrame<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        pts: i64,
+        dts: u64,
+        sample_rate: u32,
+        channel_layout: ChannelLayout,
+        samples: u32,
+        config: Option<Bytes>,
+        arrival_time: Option<u32>,
+        corrupt: bool,
+        payload: Bytes,
+        source: Arc<Mpeg2TsSource<S>>,
+    ) -> Self {
+        Self {
+            pts,
+            dts,
+            sample_rate,
+            channel_layout,
+            samples,
+            config,
+            arrival_time,
+            corrupt,
+            payload,
+            source,
+        }
+    }
+}
+
+impl<S: FrameSource> EncodedFrame for Mpeg2TsAudioFrame<S> {
+    type Param = Bytes;
+
+    fn pts(&self) -> i64 {
+        self.pts
+    }
+
+    fn params(&self) -> impl Iterator<Item = &Self::Param> {
+        self.config.iter()
+    }
+}
+
+impl<S: FrameSource> DataFrame for Mpeg2TsAudioFrame<S> {
+    type Source = Arc<Mpeg2TsSource<S>>;
+    type Chunk = Bytes;
+
+    fn chunks(&self) -> impl Send + Iterator<Item = &Bytes> {
+        std::iter::once(&self.payload)
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Bytes> {
+        std::iter::once(self.payload)
+    }
+
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+}
+
+impl<S: FrameSource> Frame for Mpeg2TsAudioFrame<S> {
+    fn timestamp(&self) -> u64 {
+        self.dts
+    }
+
+    fn codec(&self) -> Fourcc {
+        self.source.codec
+    }
+
+    fn flags(&self) -> FrameFlags {
+        // every audio frame can be decoded on its own
+        FrameFlags::KEYFRAME | FrameFlags::ENCODED | FrameFlags::AUDIO_STREAM
+    }
+}
+
+/// A frame of any elementary stream produced by the demuxer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mpeg2TsDemuxFrame<S: FrameSource> {
+    Video(Mpeg2TsFrame<S>),
+    Audio(Mpeg2TsAudioFrame<S>),
+}
+
+impl<S: FrameSource> Mpeg2TsDemuxFrame<S> {
+    pub fn payload(&self) -> &Bytes {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => &frame.payload,
+            Mpeg2TsDemuxFrame::Audio(frame) => &frame.payload,
+        }
+    }
+
+    pub fn arrival_time(&self) -> Option<u32> {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.arrival_time,
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.arrival_time,
+        }
+    }
+
+    /// `true` if packets carrying the frame were lost.
+    pub fn is_corrupt(&self) -> bool {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.corrupt,
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.corrupt,
+        }
+    }
+}
+
+impl<S: FrameSource> EncodedFrame for Mpeg2TsDemuxFrame<S> {
+    type Param = Bytes;
+
+    fn pts(&self) -> i64 {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.pts,
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.pts,
+        }
+    }
+
+    fn params(&self) -> impl Iterator<Item = &Self::Param> {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.source.params.iter(),
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.config.as_slice().iter(),
+        }
+    }
+}
+
+impl<S: FrameSource> DataFrame for Mpeg2TsDemuxFrame<S> {
+    type Source = Arc<Mpeg2TsSource<S>>;
+    type Chunk = Bytes;
+
+    fn chunks(&self) -> impl Send + Iterator<Item = &Bytes> {
+        std::iter::once(self.payload())
+    }
+
+    fn into_chunks(self) -> impl Send + Iterator<Item = Bytes> {
+        std::iter::once(match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.payload,
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.payload,
+        })
+    }
+
+    fn source(&self) -> &Self::Source {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => &frame.source,
+            Mpeg2TsDemuxFrame::Audio(frame) => &frame.source,
+        }
+    }
+}
+
+impl<S: FrameSource> Frame for Mpeg2TsDemuxFrame<S> {
+    fn timestamp(&self) -> u64 {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.timestamp(),
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.timestamp(),
+        }
+    }
+
+    fn codec(&self) -> Fourcc {
+        self.source().codec
+    }
+
+    fn flags(&self) -> FrameFlags {
+        match self {
+            Mpeg2TsDemuxFrame::Video(frame) => frame.flags(),
+            Mpeg2TsDemuxFrame::Audio(frame) => frame.flags(),
+        }
     }
 }
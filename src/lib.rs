@@ -1,9 +1,15 @@
-// mod demux;
+mod demux;
 mod error;
+mod format;
 mod frame;
 mod mpegts;
 mod muxer;
 
-// pub use demux::Mpeg2TsDemux;
+pub use demux::{
+    KeyframeDetection, Mpeg2TsDemux, Mpeg2TsDemuxConfig, Mpeg2TsDemuxEvent, Mpeg2TsDemuxEvents,
+    ProgramInfo, ProgramSelection, StreamInfo,
+};
 pub use error::Error;
-pub use muxer::Mpeg2TsMuxer;
+pub use format::PacketFormat;
+pub use frame::{ChannelLayout, Mpeg2TsAudioFrame, Mpeg2TsDemuxFrame, Mpeg2TsFrame, Mpeg2TsSource};
+pub use muxer::{Mpeg2TsMuxer, Mpeg2TsMuxerConfig};
@@ -1,14 +1,23 @@
-mod bytes;
-mod continuity_counter;
+pub mod bytes;
+pub mod continuity_counter;
 mod crc32;
-mod legal_time_window;
-mod pid;
-mod piecewise_rate;
-mod seamless_splice;
-mod stream_id;
-mod stream_type;
-mod timestamp;
-mod version;
+pub mod pid;
+pub mod section_assembler;
+pub mod stream_type;
+pub mod table_cache;
+pub mod timestamp;
+
+// value types of the original mpeg2ts API, not all of it is used by the crate
+#[allow(dead_code)]
+pub mod legal_time_window;
+#[allow(dead_code)]
+pub mod piecewise_rate;
+#[allow(dead_code)]
+pub mod seamless_splice;
+#[allow(dead_code)]
+pub mod stream_id;
+#[allow(dead_code)]
+pub mod version;
 
 pub mod io;
 pub mod ts;
@@ -24,14 +24,11 @@ impl RawData {
     /// # Errors
     ///
     /// If the length of `bytes` exceeds `Bytes::MAX_SIZE`,
-    /// it will return an `ErrorKind::InvalidInput` error.
+    /// it will return an `Error::ValueTooLarge` error.
     pub fn new(bytes: &[u8]) -> Result<Self, Error> {
-        assert!(
-            bytes.len() <= Self::MAX_SIZE,
-            "Too large: actual={} bytes, max={} bytes",
-            bytes.len(),
-            Self::MAX_SIZE
-        );
+        if bytes.len() > Self::MAX_SIZE {
+            return Err(Error::ValueTooLarge(bytes.len() as u64));
+        }
 
         let len = bytes.len();
         let mut buf = [0; Self::MAX_SIZE];
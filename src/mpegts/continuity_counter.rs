@@ -16,9 +16,11 @@ impl ContinuityCounter {
     ///
     /// # Errors
     ///
-    /// If `n` exceeds `ContinuityCounter::MAX`, it will return an `ErrorKind::InvalidInput` error.
+    /// If `n` exceeds `ContinuityCounter::MAX`, it will return an `Error::ValueTooLarge` error.
     pub fn from_u8(n: u8) -> Result<Self, Error> {
-        assert!(n <= Self::MAX, "Too large counter: {}", n);
+        if n > Self::MAX {
+            return Err(Error::ValueTooLarge(n.into()));
+        }
 
         Ok(ContinuityCounter(n))
     }
@@ -47,6 +49,11 @@ impl ContinuityCounter {
     pub fn increment(&mut self) {
         self.0 = (self.0 + 1) & Self::MAX;
     }
+
+    /// Decrements the counter.
+    pub fn decrement(&mut self) {
+        self.0 = (self.0 + Self::MAX) & Self::MAX;
+    }
 }
 impl Default for ContinuityCounter {
     fn default() -> Self {
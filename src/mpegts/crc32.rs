@@ -85,9 +85,15 @@ impl<T: Buf> Buf for WithCrc32<T> {
         self.stream.chunk()
     }
 
-    fn advance(&mut self, cnt: usize) {
-        self.crc32.update(&self.stream.chunk()[..cnt]);
-        self.stream.advance(cnt);
+    fn advance(&mut self, mut cnt: usize) {
+        while cnt > 0 && self.stream.has_remaining() {
+            let chunk = self.stream.chunk();
+            let n = chunk.len().min(cnt);
+
+            self.crc32.update(&chunk[..n]);
+            self.stream.advance(n);
+            cnt -= n;
+        }
     }
 }
 
@@ -1,6 +1,9 @@
-use std::{collections::HashMap, marker::PhantomData};
+use std::{
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+};
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::{
     Error,
@@ -8,7 +11,7 @@ use crate::{
         WritableLen,
         stream_id::StreamId,
         stream_type::StreamType,
-        ts::{MAX_SYNTAX_SECTION_LEN, PACKET_START_CODE_PREFIX, Psi, Stuffing, TsPayload},
+        ts::{PACKET_START_CODE_PREFIX, Psi, Stuffing, TsPayload, max_section_len},
         version::VersionNumber,
     },
 };
@@ -21,16 +24,198 @@ use super::{
     pid::{Pid, PidKind},
     piecewise_rate::PiecewiseRate,
     seamless_splice::SeamlessSplice,
+    section_assembler::SectionAssembler,
+    table_cache::TableCache,
     timestamp::{Clock, ESCR, PCR, PtsDts, Timestamp},
     ts::{
-        AdaptationExtensionField, AdaptationField, AdaptationFieldControl, Descriptor, EsInfo,
-        Null, Pat, Pes, PesHeader, Pmt, ProgramAssociation, PsiTable, PsiTableHeader,
-        PsiTableSyntax, Section, TransportScramblingControl, TsHeader, TsPacket,
+        AdaptationExtensionField, AdaptationField, AdaptationFieldControl, Descriptor,
+        DsmTrickMode, EsInfo, Null, PStdBuffer, Pat, Pes, PesExtension, PesHeader, Pmt,
+        ProgramAssociation, ProgramPacketSequenceCounter, PsiTable, PsiTableHeader, PsiTableSyntax,
+        Section, TransportScramblingControl, TsHeader, TsPacket, VersionedTable,
     },
 };
 
+/// Tag of the registration descriptor holding a `format_identifier`.
+const REGISTRATION_DESCRIPTOR_TAG: u8 = 0x05;
+
+#[derive(Debug, Default)]
 pub struct Mpeg2tsParser {
     pids: HashMap<Pid, PidKind>,
+    sections: HashMap<Pid, SectionAssembler>,
+    table_cache: TableCache,
+    tables: VecDeque<(Pid, VersionedTable)>,
+    private_sections: VecDeque<(Pid, PsiTable)>,
+}
+
+impl Mpeg2tsParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if a PMT announced `pid` as carrying PES packets.
+    pub fn is_pes_pid(&self, pid: Pid) -> bool {
+        matches!(self.pids.get(&pid), Some(PidKind::Pes))
+    }
+
+    /// Takes the next new or updated PSI table reassembled by `parse`.
+    pub fn pop_table(&mut self) -> Option<(Pid, VersionedTable)> {
+        self.tables.pop_front()
+    }
+
+    /// Takes the next short-form section (e.g. SCTE-35 or TDT) reassembled by
+    /// `parse`. These carry no version and are returned every time.
+    pub fn pop_private_section(&mut self) -> Option<(Pid, PsiTable)> {
+        self.private_sections.pop_front()
+    }
+
+    /// Decodes a program association table section.
+    pub fn decode_pat(&mut self, syntax: &PsiTableSyntax) -> Result<Pat, Error> {
+        let mut reader = &syntax.table_data[..];
+        let mut table = Vec::new();
+
+        while !reader.is_empty() {
+            table.push(self.parse(&mut reader)?);
+        }
+
+        Ok(Pat {
+            transport_stream_id: syntax.table_id_extension,
+            version_number: syntax.version_number,
+            table,
+        })
+    }
+
+    /// Decodes a program map table section.
+    pub fn decode_pmt(&mut self, syntax: &PsiTableSyntax) -> Result<Pmt, Error> {
+        let mut reader = &syntax.table_data[..];
+
+        let pcr_pid: Pid = self.parse(&mut reader)?;
+        let pcr_pid = if pcr_pid.as_u16() == 0b0001_1111_1111_1111 {
+            None
+        } else {
+            Some(pcr_pid)
+        };
+
+        ensure_remaining(&reader, 2)?;
+        let n = reader.get_u16();
+
+        // reserved and unused length bits are not checked in PSI, as encoders
+        // do not always set them as they should
+        let program_info_len = (n & 0b0000_0011_1111_1111) as usize;
+        ensure_remaining(&reader, program_info_len)?;
+
+        let mut program_info = Vec::new();
+        let (mut program_info_reader, mut reader) = reader.split_at(program_info_len);
+
+        while !program_info_reader.is_empty() {
+            program_info.push(self.parse(&mut program_info_reader)?);
+        }
+
+        let mut es_info = Vec::new();
+        while !reader.is_empty() {
+            es_info.push(self.parse(&mut reader)?);
+        }
+
+        Ok(Pmt {
+            program_num: syntax.table_id_extension,
+            pcr_pid,
+            version_number: syntax.version_number,
+            program_info,
+            es_info,
+        })
+    }
+
+    /// Reads the payload of a PSI packet and passes it to `push_section`.
+    fn parse_section(
+        &mut self,
+        header: &TsHeader,
+        input: &mut impl Buf,
+    ) -> Result<TsPayload, Error> {
+        let raw: RawData = self.parse(input)?;
+        self.push_section(header, &raw)?;
+
+        if header.payload_unit_start_indicator {
+            Ok(TsPayload::Section(Section {
+                pointer_field: raw[0],
+                data: RawData::new(&raw[1..])?,
+            }))
+        } else {
+            Ok(TsPayload::Raw(raw))
+        }
+    }
+
+    /// Feeds a PSI packet payload to the PID's section assembler and queues
+    /// every complete table.
+    fn push_section(&mut self, header: &TsHeader, payload: &[u8]) -> Result<(), Error> {
+        let mut sections = Vec::new();
+
+        let mut res = self.sections.entry(header.pid).or_default().push(
+            header.continuity_counter,
+            header.payload_unit_start_indicator,
+            payload,
+            &mut sections,
+        );
+
+        for mut section in sections {
+            let queued = self
+                .parse(&mut section)
+                .and_then(|table: PsiTable| self.queue_table(header.pid, table));
+            if res.is_ok() {
+                res = queued;
+            }
+        }
+
+        res
+    }
+
+    /// Registers the PIDs announced by PATs and PMTs and queues `table` for
+    /// `pop_table` if it is new or its version changed. Short-form sections
+    /// are queued for `pop_private_section` as they are.
+    fn queue_table(&mut self, pid: Pid, table: PsiTable) -> Result<(), Error> {
+        if table.syntax.is_none() {
+            self.private_sections.push_back((pid, table));
+            return Ok(());
+        }
+
+        let Some(table) = self.table_cache.push(pid, table) else {
+            return Ok(());
+        };
+
+        for section in &table.sections {
+            match table.header.table_id {
+                Pat::TABLE_ID if pid.as_u16() == Pid::PAT => {
+                    for pa in self.decode_pat(section)?.table {
+                        // program_number 0 points at the network PID
+                        if pa.program_num != 0 {
+                            self.pids.insert(pa.program_map_pid, PidKind::Pmt);
+                        }
+                    }
+                }
+
+                Pmt::TABLE_ID => {
+                    let pmt = self.decode_pmt(section)?;
+                    let hdmv = pmt
+                        .program_info
+                        .iter()
+                        .any(|d| d.tag == REGISTRATION_DESCRIPTOR_TAG && d.data[..] == *b"HDMV");
+
+                    for es in pmt.es_info {
+                        let kind = if es.stream_type.carries_sections(hdmv) {
+                            PidKind::Section
+                        } else {
+                            PidKind::Pes
+                        };
+
+                        self.pids.insert(es.elementary_pid, kind);
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        self.tables.push_back((pid, table));
+        Ok(())
+    }
 }
 
 pub trait Io<T> {
@@ -38,8 +223,46 @@ pub trait Io<T> {
     fn serialize(&mut self, item: &T, output: &mut impl BufMut) -> Result<(), Error>;
 }
 
+/// Fails with `Error::NotEnoughData` unless `input` has at least `required` bytes left.
+#[inline]
+fn ensure_remaining(input: &impl Buf, required: usize) -> Result<(), Error> {
+    if input.remaining() < required {
+        return Err(Error::NotEnoughData {
+            required,
+            remaining: input.remaining(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Fails with `Error::UnexpectedReservedBits` unless all bits of `mask` are set to `expected`.
+#[inline]
+fn ensure_bits(n: u64, mask: u64, expected: u64) -> Result<(), Error> {
+    if n & mask != expected {
+        return Err(Error::UnexpectedReservedBits(n & mask));
+    }
+
+    Ok(())
+}
+
+/// Reads a PES header PTS or DTS, failing with `Error::UnexpectedTimestampPrefix`
+/// unless its first 4 bits are `prefix`.
+fn read_timestamp(input: &mut impl Buf, prefix: u8) -> Result<Timestamp<PtsDts>, Error> {
+    ensure_remaining(input, 5)?;
+
+    let n = input.get_uint(5);
+    if (n >> 36) as u8 != prefix {
+        return Err(Error::UnexpectedTimestampPrefix((n >> 36) as u8));
+    }
+
+    Timestamp::<PtsDts>::from_u64(n)
+}
+
 impl Io<TsHeader> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<TsHeader, Error> {
+        ensure_remaining(reader, 4)?;
+
         let sync_byte = reader.get_u8();
         if TsPacket::SYNC_BYTE != sync_byte {
             return Err(Error::WrogSyncByte);
@@ -68,20 +291,39 @@ impl Io<TsHeader> for Mpeg2tsParser {
     }
 
     fn serialize(&mut self, item: &TsHeader, output: &mut impl BufMut) -> Result<(), Error> {
-        todo!()
+        output.put_u8(TsPacket::SYNC_BYTE);
+
+        let n = ((item.transport_error_indicator as u16) << 15)
+            | ((item.payload_unit_start_indicator as u16) << 14)
+            | ((item.transport_priority as u16) << 13)
+            | item.pid.as_u16();
+
+        output.put_u16(n);
+
+        let n = (item.transport_scrambling_control.as_u8() << 6)
+            | (item.adaptation_field_control.as_u8() << 4)
+            | item.continuity_counter.as_u8();
+
+        output.put_u8(n);
+
+        Ok(())
     }
 }
 
 impl Io<Option<AdaptationField>> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<Option<AdaptationField>, Error> {
-        let adaptation_field_len = reader.get_u8();
+        ensure_remaining(reader, 1)?;
+
+        let adaptation_field_len = reader.get_u8() as usize;
         if adaptation_field_len == 0 {
             return Ok(None);
         }
 
+        ensure_remaining(reader, adaptation_field_len)?;
+
         let mut buf = [0u8; 256];
-        reader.copy_to_slice(&mut buf[0..adaptation_field_len as usize]);
-        let mut adaptation_reader = buf.as_slice();
+        reader.copy_to_slice(&mut buf[0..adaptation_field_len]);
+        let mut adaptation_reader = &buf[0..adaptation_field_len];
 
         let b = adaptation_reader.get_u8();
         let discontinuity_indicator = (b & 0b1000_0000) != 0;
@@ -106,14 +348,18 @@ impl Io<Option<AdaptationField>> for Mpeg2tsParser {
         };
 
         let splice_countdown = if splicing_point_flag {
-            Some(reader.get_i8())
+            ensure_remaining(&adaptation_reader, 1)?;
+            Some(adaptation_reader.get_i8())
         } else {
             None
         };
 
         let transport_private_data = if transport_private_data_flag {
-            let len = reader.get_u8();
-            let mut buf = vec![0; len as usize];
+            ensure_remaining(&adaptation_reader, 1)?;
+            let len = adaptation_reader.get_u8() as usize;
+
+            ensure_remaining(&adaptation_reader, len)?;
+            let mut buf = vec![0; len];
             adaptation_reader.copy_to_slice(&mut buf);
             buf
         } else {
@@ -126,8 +372,6 @@ impl Io<Option<AdaptationField>> for Mpeg2tsParser {
             None
         };
 
-        // track!(util::consume_stuffing_bytes(reader))?;
-
         Ok(Some(AdaptationField {
             discontinuity_indicator,
             random_access_indicator,
@@ -145,16 +389,56 @@ impl Io<Option<AdaptationField>> for Mpeg2tsParser {
         item: &Option<AdaptationField>,
         output: &mut impl BufMut,
     ) -> Result<(), Error> {
-        todo!()
+        let Some(item) = item else {
+            output.put_u8(0);
+            return Ok(());
+        };
+
+        output.put_u8((item.external_size() - 1) as u8);
+
+        let n = ((item.discontinuity_indicator as u8) << 7)
+            | ((item.random_access_indicator as u8) << 6)
+            | ((item.es_priority_indicator as u8) << 5)
+            | ((item.pcr.is_some() as u8) << 4)
+            | ((item.opcr.is_some() as u8) << 3)
+            | ((item.splice_countdown.is_some() as u8) << 2)
+            | ((!item.transport_private_data.is_empty() as u8) << 1)
+            | item.extension.is_some() as u8;
+
+        output.put_u8(n);
+
+        if let Some(ref pcr) = item.pcr {
+            self.serialize(pcr, output)?;
+        }
+
+        if let Some(ref opcr) = item.opcr {
+            self.serialize(opcr, output)?;
+        }
+
+        if let Some(splice_countdown) = item.splice_countdown {
+            output.put_i8(splice_countdown);
+        }
+
+        if !item.transport_private_data.is_empty() {
+            output.put_u8(item.transport_private_data.len() as u8);
+            output.put_slice(&item.transport_private_data);
+        }
+
+        if let Some(ref extension) = item.extension {
+            self.serialize(extension, output)?;
+        }
+
+        Ok(())
     }
 }
 
 impl Io<Timestamp<PtsDts>> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<Timestamp<PtsDts>, Error> {
+        ensure_remaining(reader, 5)?;
+
         let n = reader.get_uint(5);
-        // assert_eq!((n >> 36) as u8, check_bits);
 
-        Ok(Timestamp::<PtsDts>::from_u64(n)?)
+        Timestamp::<PtsDts>::from_u64(n)
     }
 
     fn serialize(
@@ -162,12 +446,15 @@ impl Io<Timestamp<PtsDts>> for Mpeg2tsParser {
         item: &Timestamp<PtsDts>,
         output: &mut impl BufMut,
     ) -> Result<(), Error> {
-        todo!()
+        output.put_uint(item.to_u64(0), 5);
+        Ok(())
     }
 }
 
 impl Io<Timestamp<Clock<PCR>>> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<Timestamp<Clock<PCR>>, Error> {
+        ensure_remaining(reader, 6)?;
+
         let n = reader.get_uint(6);
         let base = n >> 15;
         let extension = n & 0b1_1111_1111;
@@ -180,23 +467,29 @@ impl Io<Timestamp<Clock<PCR>>> for Mpeg2tsParser {
         item: &Timestamp<Clock<PCR>>,
         output: &mut impl BufMut,
     ) -> Result<(), Error> {
-        todo!()
+        let base = item.0 / 300;
+        let extension = item.0 % 300;
+
+        output.put_uint((base << 15) | (0b11_1111 << 9) | extension, 6);
+        Ok(())
     }
 }
 
 impl Io<Timestamp<Clock<ESCR>>> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<Timestamp<Clock<ESCR>>, Error> {
+        ensure_remaining(reader, 6)?;
+
+        // the two leading reserved bits are ignored
         let n = reader.get_uint(6);
-        assert_eq!(n >> 46, 0);
-        assert_eq!(n & 1, 1);
+
+        const MARKER_BITS: u64 = 1 | 1 << 10 | 1 << 26 | 1 << 42;
+        if n & MARKER_BITS != MARKER_BITS {
+            return Err(Error::UnexpectedMarkerBit(n & MARKER_BITS));
+        }
 
         let extension = (n >> 1) & 0b1_1111_1111;
         let n = n >> 10;
 
-        assert_eq!(n & 1, 1);
-        assert_eq!((n >> 16) & 1, 1);
-        assert_eq!((n >> 32) & 1, 1);
-
         let n0 = (n >> 1) & ((1 << 15) - 1);
         let n1 = (n >> 17) & ((1 << 15) - 1);
         let n2 = (n >> 33) & ((1 << 3) - 1);
@@ -210,18 +503,33 @@ impl Io<Timestamp<Clock<ESCR>>> for Mpeg2tsParser {
         item: &Timestamp<Clock<ESCR>>,
         output: &mut impl BufMut,
     ) -> Result<(), Error> {
-        todo!()
+        let base = item.0 / 300;
+        let extension = item.0 % 300;
+
+        let n = (((base >> 30) & ((1 << 3) - 1)) << 33)
+            | (1 << 32)
+            | (((base >> 15) & ((1 << 15) - 1)) << 17)
+            | (1 << 16)
+            | ((base & ((1 << 15) - 1)) << 1)
+            | 1;
+
+        output.put_uint((0b11 << 46) | (n << 10) | (extension << 1) | 1, 6);
+        Ok(())
     }
 }
 
 impl Io<AdaptationExtensionField> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<AdaptationExtensionField, Error> {
-        let extension_len = reader.get_u8();
+        ensure_remaining(reader, 1)?;
+        let extension_len = reader.get_u8() as usize;
+
+        ensure_remaining(reader, extension_len)?;
 
         let mut buf = [0u8; 256];
-        reader.copy_to_slice(&mut buf[0..extension_len as usize]);
-        let mut ext_reader = buf.as_slice();
+        reader.copy_to_slice(&mut buf[0..extension_len]);
+        let mut ext_reader = &buf[0..extension_len];
 
+        ensure_remaining(&ext_reader, 1)?;
         let b = ext_reader.get_u8();
         let legal_time_window_flag = (b & 0b1000_0000) != 0;
         let piecewise_rate_flag = (b & 0b0100_0000) != 0;
@@ -255,29 +563,51 @@ impl Io<AdaptationExtensionField> for Mpeg2tsParser {
         item: &AdaptationExtensionField,
         output: &mut impl BufMut,
     ) -> Result<(), Error> {
-        todo!()
+        output.put_u8((item.external_size() - 1) as u8);
+
+        let n = ((item.legal_time_window.is_some() as u8) << 7)
+            | ((item.piecewise_rate.is_some() as u8) << 6)
+            | ((item.seamless_splice.is_some() as u8) << 5)
+            | 0b0001_1111;
+
+        output.put_u8(n);
+
+        if let Some(ref x) = item.legal_time_window {
+            self.serialize(x, output)?;
+        }
+
+        if let Some(ref x) = item.piecewise_rate {
+            self.serialize(x, output)?;
+        }
+
+        if let Some(ref x) = item.seamless_splice {
+            self.serialize(x, output)?;
+        }
+
+        Ok(())
     }
 }
 
 impl Io<Pid> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<Pid, Error> {
+        ensure_remaining(input, 2)?;
+
+        // the reserved bits in front of the PID are ignored
         let n = input.get_u16();
-        assert_eq!(
-            n & 0b1110_0000_0000_0000,
-            0b1110_0000_0000_0000,
-            "Unexpected reserved bits"
-        );
 
         Ok(Pid(n & 0b0001_1111_1111_1111))
     }
 
     fn serialize(&mut self, item: &Pid, output: &mut impl BufMut) -> Result<(), Error> {
-        todo!()
+        output.put_u16(0b1110_0000_0000_0000 | item.as_u16());
+        Ok(())
     }
 }
 
 impl Io<LegalTimeWindow> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<LegalTimeWindow, Error> {
+        ensure_remaining(input, 2)?;
+
         let n = input.get_u16();
 
         Ok(LegalTimeWindow {
@@ -295,6 +625,8 @@ impl Io<LegalTimeWindow> for Mpeg2tsParser {
 
 impl Io<PiecewiseRate> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<PiecewiseRate, Error> {
+        ensure_remaining(input, 3)?;
+
         let n = input.get_uint(3) as u32;
 
         Ok(PiecewiseRate(n & 0x3FFF_FFFF))
@@ -308,6 +640,8 @@ impl Io<PiecewiseRate> for Mpeg2tsParser {
 
 impl Io<SeamlessSplice> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<SeamlessSplice, Error> {
+        ensure_remaining(input, 5)?;
+
         let n = input.get_uint(5);
 
         Ok(SeamlessSplice {
@@ -317,9 +651,7 @@ impl Io<SeamlessSplice> for Mpeg2tsParser {
     }
 
     fn serialize(&mut self, item: &SeamlessSplice, output: &mut impl BufMut) -> Result<(), Error> {
-        self.serialize(&item.dts_next_access_unit, output)?;
-
-        // .write_to(&mut writer, self.splice_type)
+        output.put_uint(item.dts_next_access_unit.to_u64(item.splice_type), 5);
         Ok(())
     }
 }
@@ -337,31 +669,21 @@ impl Io<TsPacket> for Mpeg2tsParser {
 
         let payload = if header.adaptation_field_control.has_payload() {
             let payload = match header.pid.as_u16() {
-                Pid::PAT => {
-                    let pat: Pat = self.parse(input)?;
-                    for pa in &pat.table {
-                        self.pids.insert(pa.program_map_pid, PidKind::Pmt);
-                    }
-
-                    TsPayload::Pat(pat)
-                }
+                Pid::PAT => self.parse_section(&header, input)?,
 
                 // Null packets
                 Pid::NULL => TsPayload::Null(self.parse(input)?),
 
+                // CAT, TSDT, DVB SI (NIT, SDT, EIT, RST, TDT, DIT, SIT) and ATSC PSIP
+                0x01 | 0x02 | 0x10..=0x14 | 0x1E | 0x1F | 0x1FFB => {
+                    self.parse_section(&header, input)?
+                }
+
                 // Unknown (unsupported) packets
-                0x01..=0x1F | 0x1FFB => TsPayload::Raw(self.parse(input)?),
+                0x03..=0x0F | 0x15..=0x1D => TsPayload::Raw(self.parse(input)?),
 
                 pid => match self.pids.get(&header.pid).ok_or(Error::UnknownPid(pid))? {
-                    PidKind::Pmt => {
-                        let pmt: Pmt = self.parse(input)?;
-
-                        for es in &pmt.es_info {
-                            self.pids.insert(es.elementary_pid, PidKind::Pes);
-                        }
-
-                        TsPayload::Pmt(pmt)
-                    }
+                    PidKind::Pmt | PidKind::Section => self.parse_section(&header, input)?,
                     PidKind::Pes => {
                         if header.payload_unit_start_indicator {
                             TsPayload::Pes(self.parse(input)?)
@@ -385,6 +707,9 @@ impl Io<TsPacket> for Mpeg2tsParser {
 
     fn serialize(&mut self, item: &TsPacket, writer: &mut impl BufMut) -> Result<(), Error> {
         let payload_len = item.payload.as_ref().map(|x| x.writable_len()).unwrap_or(0);
+        if payload_len > RawData::MAX_SIZE {
+            return Err(Error::ValueTooLarge(payload_len as u64));
+        }
 
         let required_len = item
             .adaptation_field
@@ -392,12 +717,9 @@ impl Io<TsPacket> for Mpeg2tsParser {
             .map_or(0, |a| a.external_size());
 
         let free_len = TsPacket::SIZE - 4 - payload_len;
-        assert!(
-            required_len <= free_len,
-            "No space for adaptation field: required={}, free={}",
-            required_len,
-            free_len,
-        );
+        if required_len > free_len {
+            return Err(Error::ValueTooLarge(required_len as u64));
+        }
 
         let mut header = item.header.clone();
 
@@ -408,7 +730,7 @@ impl Io<TsPacket> for Mpeg2tsParser {
             (true, true) => AdaptationFieldControl::AdaptationFieldAndPayload,
             (true, false) => AdaptationFieldControl::AdaptationFieldOnly,
             (false, true) => AdaptationFieldControl::PayloadOnly,
-            (false, false) => panic!("Reserved for future use"),
+            (false, false) => unreachable!("either an adaptation field or a payload is present"),
         };
 
         header.payload_unit_start_indicator = !matches!(
@@ -417,12 +739,23 @@ impl Io<TsPacket> for Mpeg2tsParser {
         );
 
         self.serialize(&header, writer)?;
-        // let adaptation_field_len = (free_len - 1) as u8;
-        self.serialize(&item.adaptation_field, writer)?;
 
-        if item.adaptation_field.is_none() {
-            let adaptation_field_len = (free_len - 1) as u8;
-            self.serialize(&Stuffing(0xFF, adaptation_field_len as usize), writer)?;
+        match item.adaptation_field {
+            _ if free_len == 0 => {}
+            None if free_len == 1 => writer.put_u8(0),
+            None => {
+                writer.put_u8((free_len - 1) as u8);
+                writer.put_u8(0);
+                self.serialize(&Stuffing(0xFF, free_len - 2), writer)?;
+            }
+            Some(_) => {
+                let mut buf = BytesMut::with_capacity(free_len);
+                self.serialize(&item.adaptation_field, &mut buf)?;
+                buf[0] = (free_len - 1) as u8;
+
+                writer.put_slice(&buf);
+                self.serialize(&Stuffing(0xFF, free_len - buf.len()), writer)?;
+            }
         }
 
         if let Some(payload) = &item.payload {
@@ -441,8 +774,14 @@ impl Io<TsPacket> for Mpeg2tsParser {
 }
 
 impl Io<Stuffing> for Mpeg2tsParser {
-    fn parse(&mut self, _input: &mut impl Buf) -> Result<Stuffing, Error> {
-        todo!()
+    fn parse(&mut self, input: &mut impl Buf) -> Result<Stuffing, Error> {
+        let mut len = 0;
+        while input.has_remaining() && input.chunk()[0] == 0xFF {
+            input.advance(1);
+            len += 1;
+        }
+
+        Ok(Stuffing(0xFF, len))
     }
 
     fn serialize(&mut self, item: &Stuffing, output: &mut impl BufMut) -> Result<(), Error> {
@@ -453,17 +792,15 @@ impl Io<Stuffing> for Mpeg2tsParser {
 
 impl Io<RawData> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<RawData, Error> {
+        let len = input.remaining().min(RawData::MAX_SIZE);
         let mut buf = [0; RawData::MAX_SIZE];
-        input.copy_to_slice(&mut buf[..input.remaining()]);
+        input.copy_to_slice(&mut buf[..len]);
 
-        Ok(RawData {
-            buf,
-            len: input.remaining() as _,
-        })
+        Ok(RawData { buf, len })
     }
 
     fn serialize(&mut self, item: &RawData, output: &mut impl BufMut) -> Result<(), Error> {
-        output.put_slice(&item.buf[..item.len as usize]);
+        output.put_slice(&item.buf[..item.len]);
         Ok(())
     }
 }
@@ -471,72 +808,20 @@ impl Io<RawData> for Mpeg2tsParser {
 impl Io<Pmt> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<Pmt, Error> {
         let mut psi: Psi = self.parse(input)?;
+        let table = psi.tables.pop().ok_or(Error::PsiTableCountZero)?;
+        let syntax = table.syntax.ok_or(Error::MissingTableSyntax)?;
 
-        if psi.tables.len() < 1 {
-            return Err(Error::PsiTableCountZero);
-        }
-
-        let table = psi.tables.pop().expect("Never fails");
-
-        // let header = table.header;
-        // track_assert_eq!(header.table_id, Self::TABLE_ID, ErrorKind::InvalidInput);
-        // track_assert!(!header.private_bit, ErrorKind::InvalidInput);
-
-        let syntax = table.syntax.unwrap();
-        // track_assert_eq!(syntax.section_number, 0, ErrorKind::InvalidInput);
-        // track_assert_eq!(syntax.last_section_number, 0, ErrorKind::InvalidInput);
-        // track_assert!(syntax.current_next_indicator, ErrorKind::InvalidInput);
-
-        let mut reader = &syntax.table_data[..];
-
-        let pcr_pid: Pid = self.parse(&mut reader)?;
-        let pcr_pid = if pcr_pid.as_u16() == 0b0001_1111_1111_1111 {
-            None
-        } else {
-            Some(pcr_pid)
-        };
-
-        let n = reader.get_u16();
-
-        assert_eq!(
-            n & 0b1111_0000_0000_0000,
-            0b1111_0000_0000_0000,
-            "Unexpected reserved bits"
-        );
-
-        assert_eq!(
-            n & 0b0000_1100_0000_0000,
-            0,
-            "Unexpected program info length unused bits"
-        );
-
-        let program_info_len = n & 0b0000_0011_1111_1111;
-        let mut program_info = Vec::new();
-        let (mut program_info_reader, mut reader) = reader.split_at(program_info_len as usize);
-
-        while !program_info_reader.is_empty() {
-            program_info.push(self.parse(&mut program_info_reader)?);
-        }
-
-        let mut es_info = Vec::new();
-        while !reader.is_empty() {
-            es_info.push(self.parse(&mut reader)?);
-        }
-
-        Ok(Pmt {
-            program_num: syntax.table_id_extension,
-            pcr_pid,
-            version_number: syntax.version_number,
-            program_info,
-            es_info,
-        })
+        self.decode_pmt(&syntax)
     }
 
     fn serialize(&mut self, item: &Pmt, writer: &mut impl BufMut) -> Result<(), Error> {
         let mut table_data = BytesMut::new();
 
         if let Some(pid) = item.pcr_pid {
-            assert_ne!(pid.as_u16(), 0b0001_1111_1111_1111);
+            if pid.as_u16() == Pid::NULL {
+                return Err(Error::ValueTooLarge(pid.as_u16().into()));
+            }
+
             self.serialize(&pid, &mut table_data)?;
         } else {
             table_data.put_u16(0xFFFF);
@@ -548,10 +833,9 @@ impl Io<Pmt> for Mpeg2tsParser {
             .map(|desc| desc.data.len() + 2)
             .sum();
 
-        assert!(
-            program_info_len <= 0b0000_0011_1111_1111,
-            "program info length too large"
-        );
+        if program_info_len > 0b0000_0011_1111_1111 {
+            return Err(Error::ValueTooLarge(program_info_len as u64));
+        }
 
         let n = 0b1111_0000_0000_0000 | program_info_len as u16;
         table_data.put_u16(n);
@@ -581,7 +865,11 @@ impl Io<Pmt> for Mpeg2tsParser {
 
         self.serialize(
             &Psi {
-                tables: vec![PsiTable { header, syntax }],
+                tables: vec![PsiTable {
+                    header,
+                    syntax,
+                    private_data: Bytes::new(),
+                }],
             },
             writer,
         )?;
@@ -592,8 +880,15 @@ impl Io<Pmt> for Mpeg2tsParser {
 
 impl Io<Psi> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<Psi, Error> {
+        ensure_remaining(input, 1)?;
+
         let pointer_field = input.get_u8();
-        assert_eq!(pointer_field, 0);
+        if pointer_field as usize > input.remaining() {
+            return Err(Error::InvalidPointerField(pointer_field));
+        }
+
+        // tail of a section started in an earlier packet
+        input.advance(pointer_field as usize);
 
         let mut tables = Vec::new();
         while input.has_remaining() {
@@ -621,26 +916,45 @@ impl Io<PsiTable> for Mpeg2tsParser {
         let mut reader = WithCrc32::new(input);
         let (header, syntax_section_len) = self.parse(&mut reader)?;
 
-        let syntax = if header.syntax_section_indicator {
-            let syntax = self.parse(&mut (&mut reader).take(syntax_section_len as usize - 4))?;
+        let syntax_section_len = syntax_section_len as usize;
+        ensure_remaining(&reader, syntax_section_len)?;
 
-            let crc32 = reader.crc32();
-            let expected_crc32 = reader.get_u32();
+        let (syntax, private_data) = if header.syntax_section_indicator {
+            if syntax_section_len < 5 + 4 {
+                return Err(Error::NotEnoughData {
+                    required: 5 + 4,
+                    remaining: syntax_section_len,
+                });
+            }
 
-            assert_eq!(crc32, expected_crc32);
+            let syntax = self.parse(&mut (&mut reader).take(syntax_section_len - 4))?;
 
-            Some(syntax)
+            let actual = reader.crc32();
+            let expected = reader.get_u32();
+
+            if actual != expected {
+                return Err(Error::CrcMismatch { expected, actual });
+            }
+
+            (Some(syntax), Bytes::new())
         } else {
-            None
+            (None, reader.copy_to_bytes(syntax_section_len))
         };
 
-        Ok(PsiTable { header, syntax })
+        Ok(PsiTable {
+            header,
+            syntax,
+            private_data,
+        })
     }
 
     fn serialize(&mut self, item: &PsiTable, output: &mut impl BufMut) -> Result<(), Error> {
         let mut writer = WithCrc32::new(output);
 
-        let syntax_section_len = item.syntax.as_ref().map_or(0, |s| s.external_size());
+        let syntax_section_len = item
+            .syntax
+            .as_ref()
+            .map_or(item.private_data.len(), |s| s.external_size());
 
         self.serialize(&(item.header, syntax_section_len as u16), &mut writer)?;
 
@@ -649,6 +963,8 @@ impl Io<PsiTable> for Mpeg2tsParser {
 
             let crc32 = writer.crc32();
             writer.put_u32(crc32);
+        } else {
+            writer.put_slice(&item.private_data);
         }
 
         Ok(())
@@ -657,10 +973,12 @@ impl Io<PsiTable> for Mpeg2tsParser {
 
 impl Io<PsiTableSyntax> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<PsiTableSyntax, Error> {
+        ensure_remaining(input, 5)?;
+
         let table_id_extension = input.get_u16();
 
+        // reserved bits ignored
         let b = input.get_u8();
-        assert_eq!(b & 0b1100_0000, 0b1100_0000, "Unexpected reserved bits");
 
         let version_number = VersionNumber::from_u8((b & 0b0011_1110) >> 1)?;
         let current_next_indicator = (b & 0b0000_0001) != 0;
@@ -695,33 +1013,19 @@ impl Io<PsiTableSyntax> for Mpeg2tsParser {
 
 impl Io<(PsiTableHeader, u16)> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<(PsiTableHeader, u16), Error> {
+        ensure_remaining(reader, 3)?;
+
         let table_id = reader.get_u8();
         let n = reader.get_u16();
 
         let syntax_section_indicator = (n & 0b1000_0000_0000_0000) != 0;
         let private_bit = (n & 0b0100_0000_0000_0000) != 0;
 
-        assert_eq!(
-            n & 0b0011_0000_0000_0000,
-            0b0011_0000_0000_0000,
-            "Unexpected reserved bits"
-        );
-        assert_eq!(
-            n & 0b0000_1100_0000_0000,
-            0,
-            "Unexpected section length unused bits"
-        );
-
-        let syntax_section_len = n & 0b0000_0011_1111_1111;
+        let syntax_section_len = n & 0b0000_1111_1111_1111;
 
-        // track_assert!(
-        //     (syntax_section_len as usize) <= MAX_SYNTAX_SECTION_LEN,
-        //     ErrorKind::InvalidInput
-        // );
-        //
-        // if syntax_section_indicator {
-        //     track_assert_ne!(syntax_section_len, 0, ErrorKind::InvalidInput);
-        // }
+        if syntax_section_len as usize > max_section_len(table_id) {
+            return Err(Error::ValueTooLarge(syntax_section_len.into()));
+        }
 
         Ok((
             PsiTableHeader {
@@ -738,14 +1042,16 @@ impl Io<(PsiTableHeader, u16)> for Mpeg2tsParser {
         (item, syntax_section_len): &(PsiTableHeader, u16),
         writer: &mut impl BufMut,
     ) -> Result<(), Error> {
-        assert!(*syntax_section_len as usize <= MAX_SYNTAX_SECTION_LEN);
+        if *syntax_section_len as usize > max_section_len(item.table_id) {
+            return Err(Error::ValueTooLarge((*syntax_section_len).into()));
+        }
 
         writer.put_u8(item.table_id);
 
-        let n = (((*syntax_section_len != 0) as u16) << 15)
+        let n = ((item.syntax_section_indicator as u16) << 15)
             | ((item.private_bit as u16) << 14)
             | 0b0011_0000_0000_0000
-            | *syntax_section_len as u16;
+            | *syntax_section_len;
 
         writer.put_u16(n);
 
@@ -755,20 +1061,13 @@ impl Io<(PsiTableHeader, u16)> for Mpeg2tsParser {
 
 impl Io<EsInfo> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<EsInfo, Error> {
+        ensure_remaining(reader, 5)?;
+
         let stream_type = StreamType::from_u8(reader.get_u8());
         let elementary_pid: Pid = self.parse(reader)?;
 
+        // reserved and unused length bits ignored
         let n = reader.get_u16();
-        assert_eq!(
-            n & 0b1111_0000_0000_0000,
-            0b1111_0000_0000_0000,
-            "Unexpected reserved bits"
-        );
-        assert_eq!(
-            n & 0b0000_1100_0000_0000,
-            0,
-            "Unexpected ES info length unused bits"
-        );
 
         let es_info_len = n & 0b0000_0011_1111_1111;
 
@@ -790,7 +1089,9 @@ impl Io<EsInfo> for Mpeg2tsParser {
         self.serialize(&item.elementary_pid, writer)?;
 
         let es_info_len: usize = item.descriptors.iter().map(|d| 2 + d.data.len()).sum();
-        assert!(es_info_len <= 0b0011_1111_1111);
+        if es_info_len > 0b0011_1111_1111 {
+            return Err(Error::ValueTooLarge(es_info_len as u64));
+        }
 
         let n = 0b1111_0000_0000_0000 | es_info_len as u16;
         writer.put_u16(n);
@@ -805,8 +1106,11 @@ impl Io<EsInfo> for Mpeg2tsParser {
 
 impl Io<Descriptor> for Mpeg2tsParser {
     fn parse(&mut self, reader: &mut impl Buf) -> Result<Descriptor, Error> {
+        ensure_remaining(reader, 2)?;
+
         let tag = reader.get_u8();
         let len = reader.get_u8();
+        ensure_remaining(reader, len as usize)?;
 
         Ok(Descriptor {
             tag,
@@ -815,29 +1119,40 @@ impl Io<Descriptor> for Mpeg2tsParser {
     }
 
     fn serialize(&mut self, item: &Descriptor, output: &mut impl BufMut) -> Result<(), Error> {
-        todo!()
+        if item.data.len() > u8::MAX as usize {
+            return Err(Error::ValueTooLarge(item.data.len() as u64));
+        }
+
+        output.put_u8(item.tag);
+        output.put_u8(item.data.len() as u8);
+        output.put_slice(&item.data);
+        Ok(())
     }
 }
 
 impl Io<Section> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<Section, Error> {
+        ensure_remaining(input, 1)?;
+
         Ok(Section {
-            pointer_field: todo!(),
-            data: todo!(),
+            pointer_field: input.get_u8(),
+            data: self.parse(input)?,
         })
     }
 
     fn serialize(&mut self, item: &Section, output: &mut impl BufMut) -> Result<(), Error> {
-        todo!()
+        output.put_u8(item.pointer_field);
+        self.serialize(&item.data, output)
     }
 }
+
 impl Io<Null> for Mpeg2tsParser {
     fn parse(&mut self, _input: &mut impl Buf) -> Result<Null, Error> {
         Ok(Null)
     }
 
     fn serialize(&mut self, item: &Null, output: &mut impl BufMut) -> Result<(), Error> {
-        todo!()
+        self.serialize(&Stuffing(0xFF, item.writable_len()), output)
     }
 }
 impl Io<Pes> for Mpeg2tsParser {
@@ -859,33 +1174,10 @@ impl Io<Pes> for Mpeg2tsParser {
 impl Io<Pat> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<Pat, Error> {
         let mut psi: Psi = self.parse(input)?;
+        let table = psi.tables.pop().ok_or(Error::PsiTableCountZero)?;
+        let syntax = table.syntax.ok_or(Error::MissingTableSyntax)?;
 
-        if psi.tables.len() < 1 {
-            return Err(Error::PsiTableCountZero);
-        }
-
-        let table = psi.tables.pop().expect("Never fails");
-        // let header = table.header;
-        // track_assert_eq!(header.table_id, Self::TABLE_ID, ErrorKind::InvalidInput);
-        // track_assert!(!header.private_bit, ErrorKind::InvalidInput);
-
-        let syntax = table.syntax.unwrap();
-        // track_assert_eq!(syntax.section_number, 0, ErrorKind::InvalidInput);
-        // track_assert_eq!(syntax.last_section_number, 0, ErrorKind::InvalidInput);
-        // track_assert!(syntax.current_next_indicator, ErrorKind::InvalidInput);
-
-        let mut reader = &syntax.table_data[..];
-        let mut table = Vec::new();
-
-        while !input.has_remaining() {
-            table.push(self.parse(&mut reader)?);
-        }
-
-        Ok(Pat {
-            transport_stream_id: syntax.table_id_extension,
-            version_number: syntax.version_number,
-            table,
-        })
+        self.decode_pat(&syntax)
     }
 
     fn serialize(&mut self, item: &Pat, output: &mut impl BufMut) -> Result<(), Error> {
@@ -912,7 +1204,11 @@ impl Io<Pat> for Mpeg2tsParser {
 
         self.serialize(
             &Psi {
-                tables: vec![PsiTable { header, syntax }],
+                tables: vec![PsiTable {
+                    header,
+                    syntax,
+                    private_data: Bytes::new(),
+                }],
             },
             output,
         )?;
@@ -923,6 +1219,8 @@ impl Io<Pat> for Mpeg2tsParser {
 
 impl Io<ProgramAssociation> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<ProgramAssociation, Error> {
+        ensure_remaining(input, 4)?;
+
         let program_num = input.get_u16();
 
         Ok(ProgramAssociation {
@@ -944,27 +1242,61 @@ impl Io<ProgramAssociation> for Mpeg2tsParser {
 
 impl Io<PesHeader> for Mpeg2tsParser {
     fn parse(&mut self, input: &mut impl Buf) -> Result<PesHeader, Error> {
+        ensure_remaining(input, 6)?;
+
         let packet_start_code_prefix = input.get_uint(3);
-        assert_eq!(packet_start_code_prefix, PACKET_START_CODE_PREFIX);
+        if packet_start_code_prefix != PACKET_START_CODE_PREFIX {
+            return Err(Error::BadStartCode(packet_start_code_prefix));
+        }
 
         let stream_id = StreamId::new(input.get_u8());
         let packet_len = input.get_u16();
 
+        let mut header = PesHeader {
+            stream_id,
+            priority: false,
+            data_alignment_indicator: false,
+            copyright: false,
+            original_or_copy: false,
+            pts: None,
+            dts: None,
+            escr: None,
+            es_rate: None,
+            dsm_trick_mode: None,
+            additional_copy_info: None,
+            previous_pes_packet_crc: None,
+            extension: None,
+            stuffing_len: 0,
+            packet_len,
+        };
+
+        if !stream_id.has_optional_header() {
+            return Ok(header);
+        }
+
+        ensure_remaining(input, 3)?;
+
         let b = input.get_u8();
-        assert_eq!(b & 0b1100_0000, 0b1000_0000, "Unexpected marker bits");
+        if b & 0b1100_0000 != 0b1000_0000 {
+            return Err(Error::UnexpectedMarkerBit((b & 0b1100_0000).into()));
+        }
 
         let scrambling_control = (b & 0b0011_0000) >> 4;
-        let priority = (b & 0b0000_1000) != 0;
-        let data_alignment_indicator = (b & 0b0000_0100) != 0;
-        let copyright = (b & 0b0000_0010) != 0;
-        let original_or_copy = (b & 0b0000_0001) != 0;
+        header.priority = (b & 0b0000_1000) != 0;
+        header.data_alignment_indicator = (b & 0b0000_0100) != 0;
+        header.copyright = (b & 0b0000_0010) != 0;
+        header.original_or_copy = (b & 0b0000_0001) != 0;
 
-        assert_eq!(scrambling_control, 0);
+        if scrambling_control != 0 {
+            return Err(Error::UnsupportedPesFlag("PES_scrambling_control"));
+        }
 
         let b = input.get_u8();
         let pts_flag = (b & 0b1000_0000) != 0;
         let dts_flag = (b & 0b0100_0000) != 0;
-        assert_ne!((pts_flag, dts_flag), (false, true));
+        if (pts_flag, dts_flag) == (false, true) {
+            return Err(Error::UnsupportedPesFlag("PTS_DTS_flags"));
+        }
 
         let escr_flag = (b & 0b0010_0000) != 0;
         let es_rate_flag = (b & 0b0001_0000) != 0;
@@ -973,54 +1305,63 @@ impl Io<PesHeader> for Mpeg2tsParser {
         let crc_flag = (b & 0b0000_0010) != 0;
         let extension_flag = (b & 0b0000_0001) != 0;
 
-        assert!(!es_rate_flag);
-        assert!(!dsm_trick_mode_flag);
-        assert!(!additional_copy_info_flag);
-        assert!(!crc_flag);
-        assert!(!extension_flag);
+        let pes_header_len = input.get_u8() as usize;
+        ensure_remaining(input, pes_header_len)?;
 
-        let pes_header_len = input.get_u8();
-        let mut reader = input.take(pes_header_len as _);
+        let mut reader = input.take(pes_header_len);
 
-        let pts = if pts_flag {
-            let pts: Timestamp<PtsDts> = self.parse(&mut reader)?;
-            pts.check_bits(if dts_flag { 3 } else { 2 })?;
-            Some(pts)
-        } else {
-            None
-        };
+        if pts_flag {
+            let prefix = if dts_flag { 0b0011 } else { 0b0010 };
+            header.pts = Some(read_timestamp(&mut reader, prefix)?);
+        }
 
-        let dts = if dts_flag {
-            let dts: Timestamp<PtsDts> = self.parse(&mut reader)?;
-            dts.check_bits(1)?;
-            Some(dts)
-        } else {
-            None
-        };
+        if dts_flag {
+            header.dts = Some(read_timestamp(&mut reader, 0b0001)?);
+        }
 
-        let escr = if escr_flag {
-            Some(self.parse(&mut reader)?)
-        } else {
-            None
-        };
+        if escr_flag {
+            header.escr = Some(self.parse(&mut reader)?);
+        }
 
-        Ok(PesHeader {
-            stream_id,
-            priority,
-            data_alignment_indicator,
-            copyright,
-            original_or_copy,
-            pts,
-            dts,
-            escr,
-            packet_len,
-        })
+        if es_rate_flag {
+            ensure_remaining(&reader, 3)?;
+            header.es_rate = Some(((reader.get_uint(3) >> 1) & 0x3F_FFFF) as u32);
+        }
+
+        if dsm_trick_mode_flag {
+            ensure_remaining(&reader, 1)?;
+            header.dsm_trick_mode = Some(DsmTrickMode::from_u8(reader.get_u8()));
+        }
+
+        if additional_copy_info_flag {
+            ensure_remaining(&reader, 1)?;
+            header.additional_copy_info = Some(reader.get_u8() & 0b0111_1111);
+        }
+
+        if crc_flag {
+            ensure_remaining(&reader, 2)?;
+            header.previous_pes_packet_crc = Some(reader.get_u16());
+        }
+
+        if extension_flag {
+            header.extension = Some(self.parse(&mut reader)?);
+        }
+
+        // stuffing bytes
+        header.stuffing_len = reader.remaining() as u8;
+        reader.advance(reader.remaining());
+
+        Ok(header)
     }
 
     fn serialize(&mut self, item: &PesHeader, writer: &mut impl BufMut) -> Result<(), Error> {
         writer.put_uint(PACKET_START_CODE_PREFIX, 3);
         writer.put_u8(item.stream_id.as_u8());
-        writer.put_u16(todo!());
+        writer.put_u16(item.packet_len);
+
+        if !item.stream_id.has_optional_header() {
+            return Ok(());
+        }
 
         let n = 0b1000_0000
             | ((item.priority as u8) << 3)
@@ -1030,32 +1371,444 @@ impl Io<PesHeader> for Mpeg2tsParser {
 
         writer.put_u8(n);
 
-        if item.dts.is_some() {
-            assert!(item.pts.is_some());
+        if item.dts.is_some() && item.pts.is_none() {
+            return Err(Error::UnsupportedPesFlag("PTS_DTS_flags"));
         }
 
         let n = ((item.pts.is_some() as u8) << 7)
             | ((item.dts.is_some() as u8) << 6)
-            | ((item.escr.is_some() as u8) << 5);
+            | ((item.escr.is_some() as u8) << 5)
+            | ((item.es_rate.is_some() as u8) << 4)
+            | ((item.dsm_trick_mode.is_some() as u8) << 3)
+            | ((item.additional_copy_info.is_some() as u8) << 2)
+            | ((item.previous_pes_packet_crc.is_some() as u8) << 1)
+            | item.extension.is_some() as u8;
 
         writer.put_u8(n);
 
-        let pes_header_len = item.optional_header_len() as u8 - 3;
-        writer.put_u8(pes_header_len);
+        let pes_header_len = item.optional_header_len() - 3;
+        if pes_header_len > u8::MAX as u16 {
+            return Err(Error::ValueTooLarge(pes_header_len.into()));
+        }
+
+        writer.put_u8(pes_header_len as u8);
 
         if let Some(x) = item.pts {
             let check_bits = if item.dts.is_some() { 3 } else { 2 };
-            self.serialize(&x, writer)?;
+            writer.put_uint(x.to_u64(check_bits), 5);
         }
 
         if let Some(x) = item.dts {
-            let check_bits = 1;
-            self.serialize(&x, writer)?;
+            writer.put_uint(x.to_u64(1), 5);
         }
+
         if let Some(x) = item.escr {
             self.serialize(&x, writer)?;
         }
 
+        if let Some(x) = item.es_rate {
+            writer.put_uint((1 << 23) | (u64::from(x & 0x3F_FFFF) << 1) | 1, 3);
+        }
+
+        if let Some(x) = item.dsm_trick_mode {
+            writer.put_u8(x.as_u8());
+        }
+
+        if let Some(x) = item.additional_copy_info {
+            writer.put_u8(0b1000_0000 | (x & 0b0111_1111));
+        }
+
+        if let Some(x) = item.previous_pes_packet_crc {
+            writer.put_u16(x);
+        }
+
+        if let Some(ref x) = item.extension {
+            self.serialize(x, writer)?;
+        }
+
+        writer.put_bytes(0xFF, item.stuffing_len as usize);
+
+        Ok(())
+    }
+}
+
+impl Io<PesExtension> for Mpeg2tsParser {
+    fn parse(&mut self, input: &mut impl Buf) -> Result<PesExtension, Error> {
+        ensure_remaining(input, 1)?;
+
+        let b = input.get_u8();
+        let private_data_flag = (b & 0b1000_0000) != 0;
+        let pack_header_field_flag = (b & 0b0100_0000) != 0;
+        let program_packet_sequence_counter_flag = (b & 0b0010_0000) != 0;
+        let p_std_buffer_flag = (b & 0b0001_0000) != 0;
+        let extension_flag_2 = (b & 0b0000_0001) != 0;
+
+        let private_data = if private_data_flag {
+            ensure_remaining(input, 16)?;
+
+            let mut buf = [0; 16];
+            input.copy_to_slice(&mut buf);
+            Some(buf)
+        } else {
+            None
+        };
+
+        let pack_header = if pack_header_field_flag {
+            ensure_remaining(input, 1)?;
+            let len = input.get_u8() as usize;
+
+            ensure_remaining(input, len)?;
+            Some(input.copy_to_bytes(len))
+        } else {
+            None
+        };
+
+        let program_packet_sequence_counter = if program_packet_sequence_counter_flag {
+            ensure_remaining(input, 2)?;
+
+            let counter = input.get_u8() & 0b0111_1111;
+            let b = input.get_u8();
+
+            Some(ProgramPacketSequenceCounter {
+                counter,
+                mpeg1_mpeg2_identifier: (b & 0b0100_0000) != 0,
+                original_stuff_length: b & 0b0011_1111,
+            })
+        } else {
+            None
+        };
+
+        let p_std_buffer = if p_std_buffer_flag {
+            ensure_remaining(input, 2)?;
+
+            let n = input.get_u16();
+            ensure_bits(n.into(), 0b1100_0000_0000_0000, 0b0100_0000_0000_0000)?;
+
+            Some(PStdBuffer {
+                scale: (n & 0b0010_0000_0000_0000) != 0,
+                size: n & 0b0001_1111_1111_1111,
+            })
+        } else {
+            None
+        };
+
+        let mut stream_id_extension = None;
+        let mut tref = None;
+
+        if extension_flag_2 {
+            ensure_remaining(input, 1)?;
+            let len = (input.get_u8() & 0b0111_1111) as usize;
+
+            ensure_remaining(input, len)?;
+            let mut reader = input.take(len);
+
+            if reader.has_remaining() {
+                let b = reader.get_u8();
+
+                if b & 0b1000_0000 == 0 {
+                    stream_id_extension = Some(b & 0b0111_1111);
+                } else if b & 0b0000_0001 == 0 {
+                    tref = Some(self.parse(&mut reader)?);
+                }
+            }
+
+            // reserved bytes
+            reader.advance(reader.remaining());
+        }
+
+        Ok(PesExtension {
+            private_data,
+            pack_header,
+            program_packet_sequence_counter,
+            p_std_buffer,
+            stream_id_extension,
+            tref,
+        })
+    }
+
+    fn serialize(&mut self, item: &PesExtension, output: &mut impl BufMut) -> Result<(), Error> {
+        let extension_2_len = item.extension_2_len();
+
+        let n = ((item.private_data.is_some() as u8) << 7)
+            | ((item.pack_header.is_some() as u8) << 6)
+            | ((item.program_packet_sequence_counter.is_some() as u8) << 5)
+            | ((item.p_std_buffer.is_some() as u8) << 4)
+            | 0b0000_1110
+            | extension_2_len.is_some() as u8;
+
+        output.put_u8(n);
+
+        if let Some(ref x) = item.private_data {
+            output.put_slice(x);
+        }
+
+        if let Some(ref x) = item.pack_header {
+            if x.len() > u8::MAX as usize {
+                return Err(Error::ValueTooLarge(x.len() as u64));
+            }
+
+            output.put_u8(x.len() as u8);
+            output.put_slice(x);
+        }
+
+        if let Some(x) = item.program_packet_sequence_counter {
+            output.put_u8(0b1000_0000 | (x.counter & 0b0111_1111));
+            output.put_u8(
+                0b1000_0000
+                    | ((x.mpeg1_mpeg2_identifier as u8) << 6)
+                    | (x.original_stuff_length & 0b0011_1111),
+            );
+        }
+
+        if let Some(x) = item.p_std_buffer {
+            output.put_u16(
+                0b0100_0000_0000_0000 | ((x.scale as u16) << 13) | (x.size & 0b0001_1111_1111_1111),
+            );
+        }
+
+        if let Some(len) = extension_2_len {
+            output.put_u8(0b1000_0000 | len as u8);
+
+            if let Some(x) = item.stream_id_extension {
+                output.put_u8(x & 0b0111_1111);
+            } else if let Some(x) = item.tref {
+                output.put_u8(0b1111_1110);
+                output.put_uint(x.to_u64(0b1111), 5);
+            }
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpegts::crc32::Crc32;
+    use bytes::Bytes;
+
+    /// PAT and PMT captured from an ffmpeg-muxed stream: program 1 with its
+    /// PMT on PID 0x1000, H.264 on PID 0x100 and AAC on PID 0x101.
+    const PAT: &[u8] = &[
+        0x47, 0x40, 0x00, 0x10, 0x00, 0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01,
+        0xF0, 0x00, 0x2A, 0xB1, 0x04, 0xB2,
+    ];
+
+    const PMT: &[u8] = &[
+        0x47, 0x50, 0x00, 0x10, 0x00, 0x02, 0xB0, 0x17, 0x00, 0x01, 0xC1, 0x00, 0x00, 0xE1, 0x00,
+        0xF0, 0x00, 0x1B, 0xE1, 0x00, 0xF0, 0x00, 0x0F, 0xE1, 0x01, 0xF0, 0x00, 0x2F, 0x44, 0xB9,
+        0x9B,
+    ];
+
+    fn packet(data: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::from(data);
+        buf.resize(TsPacket::SIZE, 0xFF);
+        buf
+    }
+
+    fn parse_pmt(pmt: &[u8]) -> Pmt {
+        let mut parser = Mpeg2tsParser::new();
+
+        let _: TsPacket = parser.parse(&mut packet(PAT)).unwrap();
+        let (pid, table) = parser.pop_table().unwrap();
+        assert_eq!(pid.as_u16(), Pid::PAT);
+
+        let pat = parser.decode_pat(&table.sections[0]).unwrap();
+        assert_eq!(pat.transport_stream_id, 1);
+        assert_eq!(pat.table[0].program_num, 1);
+        assert_eq!(pat.table[0].program_map_pid, Pid(0x1000));
+
+        let _: TsPacket = parser.parse(&mut packet(pmt)).unwrap();
+        let (pid, table) = parser.pop_table().unwrap();
+        assert_eq!(pid, Pid(0x1000));
+
+        parser.decode_pmt(&table.sections[0]).unwrap()
+    }
+
+    #[test]
+    fn captured_pat_pmt() {
+        let pmt = parse_pmt(PMT);
+
+        assert_eq!(pmt.program_num, 1);
+        assert_eq!(pmt.pcr_pid, Some(Pid(0x100)));
+        assert!(pmt.program_info.is_empty());
+        assert_eq!(pmt.es_info.len(), 2);
+        assert_eq!(pmt.es_info[0].stream_type, StreamType::H264);
+        assert_eq!(pmt.es_info[0].elementary_pid, Pid(0x100));
+        assert_eq!(pmt.es_info[1].stream_type, StreamType::AdtsAac);
+        assert_eq!(pmt.es_info[1].elementary_pid, Pid(0x101));
+    }
+
+    #[test]
+    fn reserved_bits_ignored() {
+        let mut pmt = PMT.to_vec();
+
+        // clear every reserved bit of the section and its PIDs and lengths
+        for (pos, value) in [
+            (6, 0x80),
+            (10, 0x01),
+            (13, 0x01),
+            (15, 0x00),
+            (18, 0x01),
+            (20, 0x00),
+            (23, 0x01),
+            (25, 0x00),
+        ] {
+            pmt[pos] = value;
+        }
+
+        let crc_pos = pmt.len() - 4;
+        let mut crc = Crc32::new();
+        crc.update(&pmt[5..crc_pos]);
+        pmt[crc_pos..].copy_from_slice(&crc.value().to_be_bytes());
+
+        assert_eq!(parse_pmt(&pmt), parse_pmt(PMT));
+    }
+
+    fn round_trip(header: &PesHeader) {
+        let mut parser = Mpeg2tsParser::default();
+        let mut buf = BytesMut::new();
+        parser.serialize(header, &mut buf).unwrap();
+
+        let mut input = buf.clone().freeze();
+        let parsed: PesHeader = parser.parse(&mut input).unwrap();
+        assert!(!input.has_remaining());
+        assert_eq!(&parsed, header);
+
+        let mut again = BytesMut::new();
+        parser.serialize(&parsed, &mut again).unwrap();
+        assert_eq!(again, buf);
+    }
+
+    fn pes_header() -> PesHeader {
+        PesHeader {
+            stream_id: StreamId::new_video(0xE0).unwrap(),
+            priority: false,
+            data_alignment_indicator: true,
+            copyright: false,
+            original_or_copy: false,
+            pts: Some(Timestamp::<PtsDts>::new(0x1_2345_6789).unwrap()),
+            dts: Some(Timestamp::<PtsDts>::new(0x1_2345_0000).unwrap()),
+            escr: None,
+            es_rate: None,
+            dsm_trick_mode: None,
+            additional_copy_info: None,
+            previous_pes_packet_crc: None,
+            extension: None,
+            stuffing_len: 0,
+            packet_len: 0,
+        }
+    }
+
+    #[test]
+    fn pes_optional_fields_round_trip() {
+        round_trip(&PesHeader {
+            priority: true,
+            copyright: true,
+            original_or_copy: true,
+            escr: Some(Timestamp::<Clock<ESCR>>::new(0x1_2345_6789 * 300 + 123).unwrap()),
+            es_rate: Some(0x2A_5A5A),
+            dsm_trick_mode: Some(DsmTrickMode::FastForward {
+                field_id: 2,
+                intra_slice_refresh: true,
+                frequency_truncation: 1,
+            }),
+            additional_copy_info: Some(0x55),
+            previous_pes_packet_crc: Some(0xBEEF),
+            extension: Some(PesExtension {
+                private_data: Some(*b"0123456789abcdef"),
+                pack_header: Some(Bytes::from_static(&[0x00, 0x00, 0x01, 0xBA])),
+                program_packet_sequence_counter: Some(ProgramPacketSequenceCounter {
+                    counter: 0x45,
+                    mpeg1_mpeg2_identifier: true,
+                    original_stuff_length: 0x15,
+                }),
+                p_std_buffer: Some(PStdBuffer {
+                    scale: true,
+                    size: 0x1234,
+                }),
+                stream_id_extension: Some(0x71),
+                tref: None,
+            }),
+            stuffing_len: 3,
+            ..pes_header()
+        });
+    }
+
+    #[test]
+    fn escr_reserved_bits() {
+        let escr = Timestamp::<Clock<ESCR>>::new(0x1_2345_6789 * 300 + 123).unwrap();
+
+        let mut parser = Mpeg2tsParser::default();
+        let mut buf = BytesMut::new();
+        parser.serialize(&escr, &mut buf).unwrap();
+        assert_eq!(buf[0] & 0xC0, 0xC0);
+
+        buf[0] &= 0x3F;
+        let parsed: Timestamp<Clock<ESCR>> = parser.parse(&mut buf.freeze()).unwrap();
+        assert_eq!(parsed, escr);
+    }
+
+    #[test]
+    fn pes_trick_modes_round_trip() {
+        for mode in [
+            DsmTrickMode::SlowMotion { rep_cntrl: 0x11 },
+            DsmTrickMode::FreezeFrame { field_id: 1 },
+            DsmTrickMode::FastReverse {
+                field_id: 3,
+                intra_slice_refresh: false,
+                frequency_truncation: 2,
+            },
+            DsmTrickMode::SlowReverse { rep_cntrl: 0x1F },
+        ] {
+            round_trip(&PesHeader {
+                dts: None,
+                dsm_trick_mode: Some(mode),
+                ..pes_header()
+            });
+        }
+    }
+
+    #[test]
+    fn pes_extension_tref_round_trip() {
+        round_trip(&PesHeader {
+            extension: Some(PesExtension {
+                private_data: None,
+                pack_header: None,
+                program_packet_sequence_counter: None,
+                p_std_buffer: None,
+                stream_id_extension: None,
+                tref: Some(Timestamp::<PtsDts>::new(0x0_8765_4321).unwrap()),
+            }),
+            ..pes_header()
+        });
+    }
+
+    #[test]
+    fn pes_timestamp_prefix_checked() {
+        let mut parser = Mpeg2tsParser::default();
+        let mut buf = BytesMut::new();
+        parser.serialize(&pes_header(), &mut buf).unwrap();
+
+        // PTS followed by a DTS must start with '0011', the DTS with '0001'
+        for (pos, prefix) in [(9, 0b0010), (14, 0b0011)] {
+            let mut bad = buf.clone();
+            bad[pos] = (prefix << 4) | (bad[pos] & 0x0F);
+
+            let res: Result<PesHeader, _> = parser.parse(&mut bad.freeze());
+            assert!(matches!(res, Err(Error::UnexpectedTimestampPrefix(p)) if p == prefix));
+        }
+
+        // a lone PTS must start with '0010'
+        let mut buf = BytesMut::new();
+        let header = PesHeader {
+            dts: None,
+            ..pes_header()
+        };
+        parser.serialize(&header, &mut buf).unwrap();
+        buf[9] = 0b0011_0000 | (buf[9] & 0x0F);
+
+        let res: Result<PesHeader, _> = parser.parse(&mut buf.freeze());
+        assert!(matches!(res, Err(Error::UnexpectedTimestampPrefix(0b0011))));
+    }
+}
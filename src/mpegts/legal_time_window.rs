@@ -18,9 +18,11 @@ impl LegalTimeWindow {
     ///
     /// # Errors
     ///
-    /// If `offset` exceeds `LegalTimeWindow::MAX_OFFSET`, it will return an `ErrorKind::InvalidInput` error.
+    /// If `offset` exceeds `LegalTimeWindow::MAX_OFFSET`, it will return an `Error::ValueTooLarge` error.
     pub fn new(is_valid: bool, offset: u16) -> Result<Self, Error> {
-        assert!(offset <= Self::MAX_OFFSET, "Too large offset: {}", offset);
+        if offset > Self::MAX_OFFSET {
+            return Err(Error::ValueTooLarge(offset.into()));
+        }
 
         Ok(LegalTimeWindow { is_valid, offset })
     }
@@ -6,6 +6,9 @@ use super::WritableLen;
 pub enum PidKind {
     Pmt,
     Pes,
+
+    /// Elementary stream carried in private sections, e.g. SCTE-35.
+    Section,
 }
 
 /// Packet Identifier.
@@ -26,9 +29,11 @@ impl Pid {
     ///
     /// # Errors
     ///
-    /// If `pid` exceeds `Pid::MAX`, it will return an `ErrorKind::InvalidInput` error.
+    /// If `pid` exceeds `Pid::MAX`, it will return an `Error::ValueTooLarge` error.
     pub fn new(pid: u16) -> Result<Self, Error> {
-        assert!(pid <= Self::MAX, "Too large PID: {}", pid);
+        if pid > Self::MAX {
+            return Err(Error::ValueTooLarge(pid.into()));
+        }
 
         Ok(Pid(pid))
     }
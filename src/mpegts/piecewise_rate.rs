@@ -14,9 +14,11 @@ impl PiecewiseRate {
     ///
     /// # Errors
     ///
-    /// If `rate` exceeds `PiecewiseRate::MAX`, it will return an `ErrorKind::InvalidInput` error.
+    /// If `rate` exceeds `PiecewiseRate::MAX`, it will return an `Error::ValueTooLarge` error.
     pub fn new(rate: u32) -> Result<Self, Error> {
-        assert!(rate <= Self::MAX, "Too large rate: {}", rate);
+        if rate > Self::MAX {
+            return Err(Error::ValueTooLarge(rate.into()));
+        }
 
         Ok(PiecewiseRate(rate))
     }
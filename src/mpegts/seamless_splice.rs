@@ -19,13 +19,11 @@ impl SeamlessSplice {
     /// # Errors
     ///
     /// If `splice_type` exceeds `SeamlessSplice::MAX_SPLICE_TYPE`,
-    /// it will return an `ErrorKind::InvalidInput` error.
+    /// it will return an `Error::ValueTooLarge` error.
     pub fn new(splice_type: u8, dts_next_access_unit: Timestamp<PtsDts>) -> Result<Self, Error> {
-        assert!(
-            splice_type <= Self::MAX_SPLICE_TYPE,
-            "Too large splice type: {}",
-            splice_type
-        );
+        if splice_type > Self::MAX_SPLICE_TYPE {
+            return Err(Error::ValueTooLarge(splice_type.into()));
+        }
 
         Ok(SeamlessSplice {
             splice_type,
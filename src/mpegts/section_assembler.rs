@@ -0,0 +1,213 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::Error;
+
+use super::{continuity_counter::ContinuityCounter, ts::max_section_len};
+
+/// Reassembles PSI sections carried on a single PID.
+///
+/// Sections may span several TS packets and a single packet may carry the tail
+/// of one section followed by the head of the next one, as signalled by the
+/// `pointer_field`.
+#[derive(Debug, Default)]
+pub struct SectionAssembler {
+    buf: BytesMut,
+    last_cc: Option<ContinuityCounter>,
+    collecting: bool,
+}
+
+impl SectionAssembler {
+    /// Feeds the payload of one TS packet and appends every completed section to `out`.
+    ///
+    /// Sections are returned whole (header, body and CRC32) and are not validated
+    /// beyond their length.
+    pub fn push(
+        &mut self,
+        cc: ContinuityCounter,
+        payload_unit_start: bool,
+        mut payload: &[u8],
+        out: &mut Vec<Bytes>,
+    ) -> Result<(), Error> {
+        if self.last_cc == Some(cc) {
+            // duplicate packet
+            return Ok(());
+        }
+
+        let continuous = self.last_cc.is_some_and(|mut last| {
+            last.increment();
+            last == cc
+        });
+
+        self.last_cc = Some(cc);
+
+        if !continuous {
+            self.reset();
+        }
+
+        if payload_unit_start {
+            if payload.is_empty() {
+                self.reset();
+                return Err(Error::NotEnoughData {
+                    required: 1,
+                    remaining: 0,
+                });
+            }
+
+            let pointer_field = payload.get_u8();
+            if pointer_field as usize > payload.len() {
+                self.reset();
+                return Err(Error::InvalidPointerField(pointer_field));
+            }
+
+            let (tail, head) = payload.split_at(pointer_field as usize);
+
+            let res = if self.collecting {
+                self.buf.extend_from_slice(tail);
+                self.drain(out)
+            } else {
+                Ok(())
+            };
+
+            self.reset();
+            self.collecting = true;
+            self.buf.extend_from_slice(head);
+
+            res?;
+        } else if self.collecting {
+            self.buf.extend_from_slice(payload);
+        }
+
+        self.drain(out)
+    }
+
+    /// Drops any partially collected section.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.collecting = false;
+    }
+
+    fn drain(&mut self, out: &mut Vec<Bytes>) -> Result<(), Error> {
+        while self.collecting {
+            match self.buf.first() {
+                // the rest of the packet is stuffing, or a section ended right at its end
+                None | Some(0xFF) => self.reset(),
+                Some(&table_id) if self.buf.len() >= 3 => {
+                    let section_len =
+                        (u16::from_be_bytes([self.buf[1], self.buf[2]]) & 0x0FFF) as usize;
+
+                    if section_len > max_section_len(table_id) {
+                        self.reset();
+                        return Err(Error::ValueTooLarge(section_len as u64));
+                    }
+
+                    if self.buf.len() < 3 + section_len {
+                        break;
+                    }
+
+                    out.push(self.buf.split_to(3 + section_len).freeze());
+                }
+                _ => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn section(table_id: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![table_id, 0xB0 | (len >> 8) as u8, len as u8];
+        data.extend((0..len).map(|i| i as u8 & 0x7F));
+        data
+    }
+
+    /// Feeds `payloads` as consecutive packets of one PID, returning the completed sections.
+    fn assemble(payloads: &[(bool, &[u8])]) -> Result<Vec<Bytes>, Error> {
+        let mut assembler = SectionAssembler::default();
+        let mut cc = ContinuityCounter::new();
+        let mut out = Vec::new();
+
+        for &(pusi, payload) in payloads {
+            assembler.push(cc, pusi, payload, &mut out)?;
+            cc.increment();
+        }
+
+        Ok(out)
+    }
+
+    /// Splits a section stream into 184 byte packet payloads, padding the last one with 0xFF.
+    fn packetize(sections: &[u8]) -> Vec<Vec<u8>> {
+        let mut stream = vec![0];
+        stream.extend_from_slice(sections);
+
+        stream
+            .chunks(184)
+            .map(|chunk| {
+                let mut payload = chunk.to_vec();
+                payload.resize(184, 0xFF);
+                payload
+            })
+            .collect()
+    }
+
+    fn assemble_all(payloads: &[Vec<u8>]) -> Result<Vec<Bytes>, Error> {
+        let payloads: Vec<_> = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i == 0, p.as_slice()))
+            .collect();
+
+        assemble(&payloads)
+    }
+
+    #[test]
+    fn section_spanning_packets() {
+        let sdt = section(0x42, 400);
+        let payloads = packetize(&sdt);
+        assert_eq!(payloads.len(), 3);
+
+        assert_eq!(assemble_all(&payloads).unwrap(), vec![sdt]);
+    }
+
+    #[test]
+    fn pointer_field_split() {
+        let first = section(0x42, 250);
+        let second = section(0x46, 40);
+
+        let mut head = vec![0];
+        head.extend_from_slice(&first[..183]);
+
+        let tail = &first[183..];
+        let mut next = vec![tail.len() as u8];
+        next.extend_from_slice(tail);
+        next.extend_from_slice(&second);
+        next.resize(184, 0xFF);
+
+        let out = assemble(&[(true, &head), (true, &next)]).unwrap();
+        assert_eq!(out, vec![first, second]);
+    }
+
+    #[test]
+    fn several_sections_in_one_packet() {
+        let sections = [section(0x00, 13), section(0x02, 23), section(0x42, 60)];
+        let payloads = packetize(&sections.concat());
+        assert_eq!(payloads.len(), 1);
+
+        assert_eq!(assemble_all(&payloads).unwrap(), sections);
+    }
+
+    #[test]
+    fn private_section_limit() {
+        let private = section(0xC0, 4000);
+        assert_eq!(assemble_all(&packetize(&private)).unwrap(), vec![private]);
+
+        let pmt = section(0x02, 1100);
+        assert!(matches!(
+            assemble_all(&packetize(&pmt)),
+            Err(Error::ValueTooLarge(1100))
+        ));
+    }
+}
@@ -17,6 +17,30 @@ impl StreamId {
     /// Maximum value of the identifiers for video streams.
     pub const VIDEO_MAX: u8 = 0xEF;
 
+    /// Identifier of the program stream map.
+    pub const PROGRAM_STREAM_MAP: u8 = 0xBC;
+
+    /// Identifier of the padding stream.
+    pub const PADDING_STREAM: u8 = 0xBE;
+
+    /// Identifier of the private stream 2.
+    pub const PRIVATE_STREAM_2: u8 = 0xBF;
+
+    /// Identifier of the ECM stream.
+    pub const ECM_STREAM: u8 = 0xF0;
+
+    /// Identifier of the EMM stream.
+    pub const EMM_STREAM: u8 = 0xF1;
+
+    /// Identifier of the DSM-CC stream.
+    pub const DSMCC_STREAM: u8 = 0xF2;
+
+    /// Identifier of the ITU-T Rec. H.222.1 type E stream.
+    pub const H222_1_TYPE_E: u8 = 0xF8;
+
+    /// Identifier of the program stream directory.
+    pub const PROGRAM_STREAM_DIRECTORY: u8 = 0xFF;
+
     /// Makes a new `StreamId` instance.
     pub fn new(id: u8) -> Self {
         StreamId(id)
@@ -41,7 +65,7 @@ impl StreamId {
     ///
     /// If `id` is not between `VIDEO_MIN` and `VIDEO_MAX`, it will return an `ErrorKind::InvalidInput` error.
     pub fn new_video(id: u8) -> Result<Self, Error> {
-        if (Self::VIDEO_MIN..=Self::VIDEO_MAX).contains(&id) {
+        if !(Self::VIDEO_MIN..=Self::VIDEO_MAX).contains(&id) {
             return Err(Error::WrongVideoStreamId(id));
         }
 
@@ -62,4 +86,19 @@ impl StreamId {
     pub fn is_video(&self) -> bool {
         0xE0 <= self.0 && self.0 <= 0xEF
     }
+
+    /// Returns `true` if PES packets of this stream carry the optional PES header.
+    pub fn has_optional_header(&self) -> bool {
+        !matches!(
+            self.0,
+            Self::PROGRAM_STREAM_MAP
+                | Self::PADDING_STREAM
+                | Self::PRIVATE_STREAM_2
+                | Self::ECM_STREAM
+                | Self::EMM_STREAM
+                | Self::PROGRAM_STREAM_DIRECTORY
+                | Self::DSMCC_STREAM
+                | Self::H222_1_TYPE_E
+        )
+    }
 }
@@ -158,4 +158,23 @@ impl StreamType {
             unk => StreamType::Unknown(unk),
         }
     }
+
+    /// Returns `true` if the stream is carried in sections rather than PES packets.
+    ///
+    /// Outside of BDAV (`hdmv`) programs, stream type 0x86 is SCTE-35 rather than
+    /// DTS-HD Master Audio.
+    pub fn carries_sections(&self, hdmv: bool) -> bool {
+        match self {
+            StreamType::Mpeg2TabledData
+            | StreamType::DsmCcMultiprotocolEncapsulation
+            | StreamType::DsmCcUnMessages
+            | StreamType::DsmCcStreamDescriptors
+            | StreamType::DsmCcTabledData
+            | StreamType::Mpeg4FlexMuxInTable
+            | StreamType::SectionedMetadata
+            | StreamType::AtscDsmCcNetworkResourcesTable => true,
+            StreamType::Dts8ChannelLosslessAudio => !hdmv,
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use super::{
+    pid::Pid,
+    ts::{PsiTable, PsiTableSyntax, VersionedTable},
+    version::VersionNumber,
+};
+
+/// Table ids of the DVB event information tables, whose sections are
+/// numbered in segments of eight.
+const EIT_TABLE_IDS: std::ops::RangeInclusive<u8> = 0x4E..=0x6F;
+
+/// Sections per EIT segment.
+const EIT_SEGMENT_LEN: usize = 8;
+
+#[derive(Debug)]
+struct Entry {
+    version_number: VersionNumber,
+    sections: Vec<Option<PsiTableSyntax>>,
+    published: Option<VersionNumber>,
+}
+
+/// Collects the sections of long-form PSI tables and publishes each table
+/// once per version.
+///
+/// Tables are keyed by PID, `table_id` and `table_id_extension`. Sections
+/// with `current_next_indicator = 0` describe a table that is not yet
+/// applicable and are ignored.
+///
+/// EIT sections skip the numbers past the `segment_last_section_number` of
+/// each segment, an EIT is published once each of its segments is complete.
+#[derive(Debug, Default)]
+pub struct TableCache {
+    entries: HashMap<(Pid, u8, u16), Entry>,
+}
+
+impl TableCache {
+    /// Adds a section and returns the table once all of its sections for a
+    /// version that has not been published yet are collected.
+    pub fn push(&mut self, pid: Pid, table: PsiTable) -> Option<VersionedTable> {
+        let syntax = table.syntax?;
+        if !syntax.current_next_indicator || syntax.section_number > syntax.last_section_number {
+            return None;
+        }
+
+        let key = (pid, table.header.table_id, syntax.table_id_extension);
+        let entry = self.entries.entry(key).or_insert_with(|| Entry {
+            version_number: syntax.version_number,
+            sections: Vec::new(),
+            published: None,
+        });
+
+        if entry.published == Some(syntax.version_number) {
+            return None;
+        }
+
+        let section_count = syntax.last_section_number as usize + 1;
+        if entry.version_number != syntax.version_number || entry.sections.len() != section_count {
+            entry.version_number = syntax.version_number;
+            entry.sections = vec![None; section_count];
+        }
+
+        let section_number = syntax.section_number as usize;
+        entry.sections[section_number] = Some(syntax);
+
+        let complete = if EIT_TABLE_IDS.contains(&table.header.table_id) {
+            eit_complete(&entry.sections)
+        } else {
+            entry.sections.iter().all(Option::is_some)
+        };
+
+        if !complete {
+            return None;
+        }
+
+        entry.published = Some(entry.version_number);
+
+        let sections: Vec<_> = entry.sections.drain(..).flatten().collect();
+
+        Some(VersionedTable {
+            header: table.header,
+            table_id_extension: key.2,
+            version_number: entry.version_number,
+            sections,
+        })
+    }
+}
+
+/// Returns `true` if every section of every EIT segment is present, up to
+/// the `segment_last_section_number` of the segment.
+fn eit_complete(sections: &[Option<PsiTableSyntax>]) -> bool {
+    sections.chunks(EIT_SEGMENT_LEN).all(|segment| {
+        let Some(last) = segment
+            .iter()
+            .flatten()
+            .next()
+            .map(segment_last_section_number)
+        else {
+            // every segment carries at least one section, empty or not
+            return false;
+        };
+
+        let count = last.map_or(segment.len(), |last| last as usize % EIT_SEGMENT_LEN + 1);
+        segment.iter().take(count).all(Option::is_some)
+    })
+}
+
+/// Reads `segment_last_section_number`, which follows the transport stream
+/// and original network ids of an EIT section.
+fn segment_last_section_number(syntax: &PsiTableSyntax) -> Option<u8> {
+    syntax.table_data.get(4).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::mpegts::ts::PsiTableHeader;
+
+    fn section(version: u8, section_number: u8, last_section_number: u8) -> PsiTable {
+        PsiTable {
+            header: PsiTableHeader {
+                table_id: 0x42,
+                private_bit: true,
+                syntax_section_indicator: true,
+            },
+            syntax: Some(PsiTableSyntax {
+                table_id_extension: 1,
+                version_number: VersionNumber::from_u8(version).unwrap(),
+                current_next_indicator: true,
+                section_number,
+                last_section_number,
+                table_data: Bytes::from(vec![section_number]),
+            }),
+            private_data: Bytes::new(),
+        }
+    }
+
+    fn eit_section(section_number: u8, segment_last: u8, last_section_number: u8) -> PsiTable {
+        let mut table = section(0, section_number, last_section_number);
+        table.header.table_id = 0x50;
+
+        let syntax = table.syntax.as_mut().unwrap();
+        syntax.table_data = Bytes::from(vec![0, 1, 0, 2, segment_last, 0x50, section_number]);
+        table
+    }
+
+    fn pid() -> Pid {
+        Pid::new(0x11).unwrap()
+    }
+
+    #[test]
+    fn published_once_per_version() {
+        let mut cache = TableCache::default();
+
+        let table = cache.push(pid(), section(3, 0, 0)).unwrap();
+        assert_eq!(table.version_number.as_u8(), 3);
+        assert_eq!(table.sections.len(), 1);
+
+        assert!(cache.push(pid(), section(3, 0, 0)).is_none());
+        assert!(cache.push(pid(), section(3, 0, 0)).is_none());
+
+        let table = cache.push(pid(), section(4, 0, 0)).unwrap();
+        assert_eq!(table.version_number.as_u8(), 4);
+        assert!(cache.push(pid(), section(4, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn multi_section_table_gathered() {
+        let mut cache = TableCache::default();
+
+        assert!(cache.push(pid(), section(0, 1, 2)).is_none());
+        assert!(cache.push(pid(), section(0, 1, 2)).is_none());
+        assert!(cache.push(pid(), section(0, 0, 2)).is_none());
+
+        let table = cache.push(pid(), section(0, 2, 2)).unwrap();
+        let data: Vec<_> = table.sections.iter().map(|s| s.table_data[0]).collect();
+        assert_eq!(data, [0, 1, 2]);
+
+        assert!(cache.push(pid(), section(0, 0, 2)).is_none());
+    }
+
+    #[test]
+    fn next_sections_ignored() {
+        let mut cache = TableCache::default();
+
+        let mut next = section(1, 0, 0);
+        next.syntax.as_mut().unwrap().current_next_indicator = false;
+        assert!(cache.push(pid(), next).is_none());
+
+        assert!(cache.push(pid(), section(1, 0, 0)).is_some());
+    }
+
+    #[test]
+    fn eit_segments_gathered() {
+        let mut cache = TableCache::default();
+        let pid = Pid::new(0x12).unwrap();
+
+        // segments of two, one and two sections
+        for (section_number, segment_last) in [(0, 1), (16, 17), (8, 8), (1, 1)] {
+            assert!(
+                cache
+                    .push(pid, eit_section(section_number, segment_last, 17))
+                    .is_none()
+            );
+        }
+
+        let table = cache.push(pid, eit_section(17, 17, 17)).unwrap();
+        let data: Vec<_> = table.sections.iter().map(|s| s.table_data[6]).collect();
+        assert_eq!(data, [0, 1, 8, 16, 17]);
+    }
+
+    #[test]
+    fn eit_missing_segment_not_published() {
+        let mut cache = TableCache::default();
+        let pid = Pid::new(0x12).unwrap();
+
+        assert!(cache.push(pid, eit_section(0, 0, 16)).is_none());
+        assert!(cache.push(pid, eit_section(16, 16, 16)).is_none());
+        assert!(cache.push(pid, eit_section(8, 8, 16)).is_some());
+    }
+}
@@ -1,4 +1,4 @@
-use std::marker::PhantomData;
+use std::{cmp::Ordering, marker::PhantomData};
 
 use crate::Error;
 
@@ -6,8 +6,10 @@ use crate::Error;
 pub struct PtsDts;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Clock<T>(PhantomData<T>);
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PCR;
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ESCR;
 
@@ -28,22 +30,17 @@ impl Timestamp<PtsDts> {
     ///
     /// If `n` exceeds `Timestamp::MAX`, it will return an `ErrorKind::InvalidInput` error.
     pub fn new(n: u64) -> Result<Self, Error> {
-        if n <= Self::MAX {
+        if n > Self::MAX {
             return Err(Error::ValueTooLarge(n));
         }
 
         Ok(Timestamp(n, PhantomData))
     }
 
-    /// Returns the value of the timestamp.
-    pub fn as_u64(&self) -> u64 {
-        self.0
-    }
-
     pub(crate) fn from_u64(n: u64) -> Result<Self, Error> {
         const MARKER_BITS: u64 = 1 | 1 << 16 | 1 << 32;
 
-        if MARKER_BITS & n != 0 {
+        if MARKER_BITS & n != MARKER_BITS {
             return Err(Error::UnexpectedMarkerBit(MARKER_BITS & n));
         }
 
@@ -54,8 +51,16 @@ impl Timestamp<PtsDts> {
         Ok(Timestamp((n0 << 30) | (n1 << 15) | n2, PhantomData))
     }
 
-    pub(crate) fn check_bits(&self, arg: u8) -> Result<(), Error> {
-        Ok(())
+    pub(crate) fn to_u64(self, check_bits: u8) -> u64 {
+        let n = self.0;
+
+        (u64::from(check_bits) << 36)
+            | (((n >> 30) & ((1 << 3) - 1)) << 33)
+            | (1 << 32)
+            | (((n >> 15) & ((1 << 15) - 1)) << 17)
+            | (1 << 16)
+            | ((n & ((1 << 15) - 1)) << 1)
+            | 1
     }
 }
 
@@ -78,17 +83,49 @@ impl<T> Timestamp<Clock<T>> {
     ///
     /// If `n` exceeds `ClockReference::MAX`, it will return an `ErrorKind::InvalidInput` error.
     pub fn new(n: u64) -> Result<Self, Error> {
-        if n <= Self::MAX {
+        if n > Self::MAX {
             return Err(Error::ValueTooLarge(n));
         }
 
         Ok(Timestamp(n, PhantomData))
     }
+}
 
-    /// Returns the value of the PCR.
+/// Timestamps that wrap around after `MODULUS` ticks.
+pub trait Wrapping: Copy {
+    const MODULUS: u64;
+}
+
+impl Wrapping for PtsDts {
+    const MODULUS: u64 = 1 << 33;
+}
+
+impl<T: Copy> Wrapping for Clock<T> {
+    // the 33-bit base times 300, the 9-bit extension never reaches 300
+    const MODULUS: u64 = (1 << 33) * 300;
+}
+
+impl<T: Wrapping> Timestamp<T> {
+    /// Returns the value of the timestamp.
     pub fn as_u64(&self) -> u64 {
         self.0
     }
+
+    /// Returns `self - other` in ticks, taking the shorter way around the wrap.
+    pub fn wrapping_sub(&self, other: &Self) -> i64 {
+        let d = (self.0 + T::MODULUS - other.0 % T::MODULUS) % T::MODULUS;
+
+        if d >= T::MODULUS / 2 {
+            d as i64 - T::MODULUS as i64
+        } else {
+            d as i64
+        }
+    }
+
+    /// Compares two timestamps less than half the range apart, across a wrap.
+    pub fn wrapping_cmp(&self, other: &Self) -> Ordering {
+        self.wrapping_sub(other).cmp(&0)
+    }
 }
 
 impl<T> From<u32> for Timestamp<Clock<T>> {
@@ -103,25 +140,127 @@ impl<T> From<Timestamp<PtsDts>> for Timestamp<Clock<T>> {
     }
 }
 
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-
-//     #[test]
-//     fn pcr_conversion() {
-//         let cr = ClockReference::new(10000).unwrap();
-//         let mut buf = Vec::new();
-//         cr.write_pcr_to(&mut buf).unwrap();
-//         let new_cr = ClockReference::read_pcr_from(&buf[..]).unwrap();
-//         assert_eq!(cr, new_cr);
-//     }
-
-//     #[test]
-//     fn escr_conversion() {
-//         let cr = ClockReference::new(10000).unwrap();
-//         let mut buf = Vec::new();
-//         cr.write_escr_to(&mut buf).unwrap();
-//         let new_cr = ClockReference::read_escr_from(&buf[..]).unwrap();
-//         assert_eq!(cr, new_cr);
-//     }
-// }
+/// Extends timestamps that wrap around into a monotonic 64-bit timeline.
+///
+/// Each value is placed at the shorter distance from the previous one, so
+/// streams must not jump by half the timestamp range or more.
+#[derive(Debug, Clone, Copy)]
+pub struct Unwrapper<T> {
+    last: Option<(Timestamp<T>, u64)>,
+}
+
+impl<T: Wrapping> Unwrapper<T> {
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Returns the last unwrapped value.
+    pub fn last(&self) -> Option<u64> {
+        self.last.map(|(_, value)| value)
+    }
+
+    /// Returns the last timestamp as it was passed to `extend`.
+    pub fn last_timestamp(&self) -> Option<Timestamp<T>> {
+        self.last.map(|(ts, _)| ts)
+    }
+
+    /// Unwraps `ts`. Values before the start of the timeline are clamped to 0.
+    pub fn extend(&mut self, ts: Timestamp<T>) -> u64 {
+        let value = match self.last {
+            None => ts.as_u64(),
+            Some((last, value)) => value.saturating_add_signed(ts.wrapping_sub(&last)),
+        };
+
+        self.last = Some((ts, value));
+        value
+    }
+}
+
+impl<T: Wrapping> Default for Unwrapper<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpegts::io::{Io, Mpeg2tsParser};
+
+    const PCR_WRAP: u64 = (1 << 33) * 300;
+
+    #[test]
+    fn pcr_conversion() {
+        let mut parser = Mpeg2tsParser::new();
+
+        for n in [0, 10000, PCR_WRAP - 1] {
+            let cr = Timestamp::<Clock<PCR>>::new(n).unwrap();
+            let mut buf = Vec::new();
+            parser.serialize(&cr, &mut buf).unwrap();
+
+            let new_cr: Timestamp<Clock<PCR>> = parser.parse(&mut &buf[..]).unwrap();
+            assert_eq!(cr, new_cr);
+        }
+    }
+
+    #[test]
+    fn escr_conversion() {
+        let mut parser = Mpeg2tsParser::new();
+
+        for n in [0, 10000, PCR_WRAP - 1] {
+            let cr = Timestamp::<Clock<ESCR>>::new(n).unwrap();
+            let mut buf = Vec::new();
+            parser.serialize(&cr, &mut buf).unwrap();
+
+            let new_cr: Timestamp<Clock<ESCR>> = parser.parse(&mut &buf[..]).unwrap();
+            assert_eq!(cr, new_cr);
+        }
+    }
+
+    #[test]
+    fn wrapping_sub_across_wrap() {
+        let pts = |n| Timestamp::<PtsDts>::new(n).unwrap();
+        assert_eq!(pts(5).wrapping_sub(&pts(Timestamp::<PtsDts>::MAX)), 6);
+        assert_eq!(pts(Timestamp::<PtsDts>::MAX).wrapping_sub(&pts(5)), -6);
+        assert_eq!(
+            pts(5).wrapping_cmp(&pts(Timestamp::<PtsDts>::MAX)),
+            Ordering::Greater
+        );
+
+        let pcr = |n| Timestamp::<Clock<PCR>>::new(n).unwrap();
+        assert_eq!(pcr(5).wrapping_sub(&pcr(PCR_WRAP - 1)), 6);
+        assert_eq!(pcr(PCR_WRAP - 1).wrapping_sub(&pcr(5)), -6);
+        assert_eq!(pcr(100).wrapping_cmp(&pcr(100)), Ordering::Equal);
+        assert_eq!(
+            pcr(0).wrapping_cmp(&pcr(PCR_WRAP / 2 + 1)),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn unwrap_across_wrap() {
+        let mut pcrs = Unwrapper::<Clock<PCR>>::new();
+        let step = 27_000;
+
+        let mut expected = PCR_WRAP - 3 * step;
+        for _ in 0..6 {
+            let pcr = Timestamp::<Clock<PCR>>::new(expected % PCR_WRAP).unwrap();
+            assert_eq!(pcrs.extend(pcr), expected);
+            expected += step;
+        }
+
+        let mut pts = Unwrapper::<PtsDts>::new();
+        assert_eq!(
+            pts.extend(Timestamp::<PtsDts>::new((1 << 33) - 10).unwrap()),
+            (1 << 33) - 10
+        );
+        assert_eq!(
+            pts.extend(Timestamp::<PtsDts>::new(20).unwrap()),
+            (1 << 33) + 20
+        );
+        assert_eq!(
+            pts.extend(Timestamp::<PtsDts>::new((1 << 33) - 30).unwrap()),
+            (1 << 33) - 30
+        );
+    }
+}
@@ -18,12 +18,12 @@ pub use stuffing::Stuffing;
 
 use super::WritableLen;
 
-/// Payload for null packets.
+/// Payload for null packets, written as a packet full of stuffing bytes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Null;
 
 impl WritableLen for Null {
     fn writable_len(&self) -> usize {
-        0
+        TsPacket::SIZE - 4
     }
 }
@@ -44,7 +44,10 @@ impl AdaptationField {
             n += 1;
         }
 
-        n += self.transport_private_data.len();
+        if !self.transport_private_data.is_empty() {
+            n += 1 /* transport_private_data_len */ + self.transport_private_data.len();
+        }
+
         if let Some(ref x) = self.extension {
             n += x.external_size();
         }
@@ -63,7 +66,7 @@ pub struct AdaptationExtensionField {
 }
 
 impl AdaptationExtensionField {
-    fn external_size(&self) -> usize {
+    pub fn external_size(&self) -> usize {
         let mut n = 1 /* length */ + 1 /* flags */;
         if self.legal_time_window.is_some() {
             n += 2;
@@ -92,11 +95,18 @@ pub enum AdaptationFieldControl {
 }
 impl AdaptationFieldControl {
     pub fn has_adaptation_field(&self) -> bool {
-        *self != AdaptationFieldControl::PayloadOnly
+        matches!(
+            self,
+            AdaptationFieldControl::AdaptationFieldOnly
+                | AdaptationFieldControl::AdaptationFieldAndPayload
+        )
     }
 
     pub fn has_payload(&self) -> bool {
-        *self != AdaptationFieldControl::AdaptationFieldOnly
+        matches!(
+            self,
+            AdaptationFieldControl::PayloadOnly | AdaptationFieldControl::AdaptationFieldAndPayload
+        )
     }
 
     pub fn from_u8(n: u8) -> Self {
@@ -108,4 +118,14 @@ impl AdaptationFieldControl {
             v => AdaptationFieldControl::Unknown(v),
         }
     }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            AdaptationFieldControl::Reserved => 0b00,
+            AdaptationFieldControl::PayloadOnly => 0b01,
+            AdaptationFieldControl::AdaptationFieldOnly => 0b10,
+            AdaptationFieldControl::AdaptationFieldAndPayload => 0b11,
+            AdaptationFieldControl::Unknown(v) => *v,
+        }
+    }
 }
@@ -29,6 +29,15 @@ impl TransportScramblingControl {
             v => TransportScramblingControl::Unknown(v),
         }
     }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            TransportScramblingControl::NotScrambled => 0b00,
+            TransportScramblingControl::ScrambledWithEvenKey => 0b10,
+            TransportScramblingControl::ScrambledWithOddKey => 0b11,
+            TransportScramblingControl::Unknown(v) => *v,
+        }
+    }
 }
 
 /// Transport stream packet.
@@ -14,7 +14,11 @@ impl Pat {
 
 impl WritableLen for Pat {
     fn writable_len(&self) -> usize {
-        todo!()
+        1 /* pointer_field */ +
+            3 /* table header */ +
+            5 /* table syntax */ +
+            self.table.iter().map(|pa| pa.writable_len()).sum::<usize>() +
+            4 /* CRC32 */
     }
 }
 
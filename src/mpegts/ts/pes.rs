@@ -1,3 +1,5 @@
+use bytes::Bytes;
+
 use crate::mpegts::{
     WritableLen,
     bytes::RawData,
@@ -15,7 +17,9 @@ pub struct Pes<B = RawData> {
 
 impl WritableLen for Pes {
     fn writable_len(&self) -> usize {
-        todo!()
+        6 /* start code, stream_id and PES_packet_length */ +
+            self.header.optional_header_len() as usize +
+            self.data.writable_len()
     }
 }
 
@@ -46,14 +50,204 @@ pub struct PesHeader {
 
     /// Elementary stream clock reference.
     pub escr: Option<Timestamp<Clock<ESCR>>>,
+
+    /// Elementary stream rate in units of 50 bytes/second.
+    pub es_rate: Option<u32>,
+
+    pub dsm_trick_mode: Option<DsmTrickMode>,
+
+    /// Private data related to copyright information (7 bits).
+    pub additional_copy_info: Option<u8>,
+
+    /// CRC-16 of the previous PES packet data bytes.
+    pub previous_pes_packet_crc: Option<u16>,
+
+    pub extension: Option<PesExtension>,
+    pub(crate) stuffing_len: u8,
     pub(crate) packet_len: u16,
 }
 
 impl PesHeader {
+    /// Length of the optional PES header, including the two flag bytes,
+    /// `PES_header_data_length` and stuffing bytes.
+    ///
+    /// Streams without the optional header (padding, private stream 2, etc.) have zero length.
     pub fn optional_header_len(&self) -> u16 {
+        if !self.stream_id.has_optional_header() {
+            return 0;
+        }
+
         3 //-
             + self.pts.map_or(0, |_| 5)
             + self.dts.map_or(0, |_| 5)
             + self.escr.map_or(0, |_| 6)
+            + self.es_rate.map_or(0, |_| 3)
+            + self.dsm_trick_mode.map_or(0, |_| 1)
+            + self.additional_copy_info.map_or(0, |_| 1)
+            + self.previous_pes_packet_crc.map_or(0, |_| 2)
+            + self.extension.as_ref().map_or(0, |x| x.writable_len() as u16)
+            + self.stuffing_len as u16
+    }
+
+    /// Number of PES packet data bytes announced by `PES_packet_length`.
+    ///
+    /// Returns `None` for unbounded packets (`PES_packet_length` is zero).
+    pub fn data_len(&self) -> Option<usize> {
+        if self.packet_len == 0 {
+            return None;
+        }
+
+        Some((self.packet_len as usize).saturating_sub(self.optional_header_len() as usize))
+    }
+}
+
+/// DSM trick mode control.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DsmTrickMode {
+    FastForward {
+        field_id: u8,
+        intra_slice_refresh: bool,
+        frequency_truncation: u8,
+    },
+    SlowMotion {
+        rep_cntrl: u8,
+    },
+    FreezeFrame {
+        field_id: u8,
+    },
+    FastReverse {
+        field_id: u8,
+        intra_slice_refresh: bool,
+        frequency_truncation: u8,
+    },
+    SlowReverse {
+        rep_cntrl: u8,
+    },
+    Reserved(u8),
+}
+
+impl DsmTrickMode {
+    pub fn from_u8(n: u8) -> Self {
+        let field_id = (n >> 3) & 0b11;
+        let intra_slice_refresh = (n & 0b100) != 0;
+        let frequency_truncation = n & 0b11;
+        let rep_cntrl = n & 0b1_1111;
+
+        match n >> 5 {
+            0b000 => DsmTrickMode::FastForward {
+                field_id,
+                intra_slice_refresh,
+                frequency_truncation,
+            },
+            0b001 => DsmTrickMode::SlowMotion { rep_cntrl },
+            0b010 => DsmTrickMode::FreezeFrame { field_id },
+            0b011 => DsmTrickMode::FastReverse {
+                field_id,
+                intra_slice_refresh,
+                frequency_truncation,
+            },
+            0b100 => DsmTrickMode::SlowReverse { rep_cntrl },
+            _ => DsmTrickMode::Reserved(n),
+        }
     }
+
+    pub fn as_u8(&self) -> u8 {
+        match *self {
+            DsmTrickMode::FastForward {
+                field_id,
+                intra_slice_refresh,
+                frequency_truncation,
+            } => {
+                ((field_id & 0b11) << 3)
+                    | ((intra_slice_refresh as u8) << 2)
+                    | (frequency_truncation & 0b11)
+            }
+            DsmTrickMode::SlowMotion { rep_cntrl } => (0b001 << 5) | (rep_cntrl & 0b1_1111),
+            DsmTrickMode::FreezeFrame { field_id } => {
+                (0b010 << 5) | ((field_id & 0b11) << 3) | 0b111
+            }
+            DsmTrickMode::FastReverse {
+                field_id,
+                intra_slice_refresh,
+                frequency_truncation,
+            } => {
+                (0b011 << 5)
+                    | ((field_id & 0b11) << 3)
+                    | ((intra_slice_refresh as u8) << 2)
+                    | (frequency_truncation & 0b11)
+            }
+            DsmTrickMode::SlowReverse { rep_cntrl } => (0b100 << 5) | (rep_cntrl & 0b1_1111),
+            DsmTrickMode::Reserved(n) => n,
+        }
+    }
+}
+
+/// PES extension fields.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PesExtension {
+    /// 16 bytes of private data.
+    pub private_data: Option<[u8; 16]>,
+
+    /// Program stream pack header carried in a transport stream.
+    pub pack_header: Option<Bytes>,
+
+    pub program_packet_sequence_counter: Option<ProgramPacketSequenceCounter>,
+    pub p_std_buffer: Option<PStdBuffer>,
+
+    /// Extension of the `stream_id` (7 bits), used with `extended_stream_id` (0xFD).
+    pub stream_id_extension: Option<u8>,
+
+    /// Timestamp reference, only written when `stream_id_extension` is absent.
+    pub tref: Option<Timestamp<PtsDts>>,
+}
+
+impl WritableLen for PesExtension {
+    fn writable_len(&self) -> usize {
+        1 /* flags */
+            + self.private_data.map_or(0, |_| 16)
+            + self.pack_header.as_ref().map_or(0, |x| 1 + x.len())
+            + self.program_packet_sequence_counter.map_or(0, |_| 2)
+            + self.p_std_buffer.map_or(0, |_| 2)
+            + self.extension_2_len().map_or(0, |n| 1 + n)
+    }
+}
+
+impl PesExtension {
+    /// Length of the data following `PES_extension_field_length`, if the field is present.
+    pub(crate) fn extension_2_len(&self) -> Option<usize> {
+        if self.stream_id_extension.is_some() {
+            Some(1)
+        } else if self.tref.is_some() {
+            Some(1 + 5)
+        } else {
+            None
+        }
+    }
+}
+
+/// Program packet sequence counter.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProgramPacketSequenceCounter {
+    /// 7-bit counter.
+    pub counter: u8,
+
+    /// `true` for ISO/IEC 11172-1 (MPEG-1), `false` for ISO/IEC 13818-1 (MPEG-2).
+    pub mpeg1_mpeg2_identifier: bool,
+
+    /// Number of stuffing bytes in the original packet header (6 bits).
+    pub original_stuff_length: u8,
+}
+
+/// P-STD buffer size.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PStdBuffer {
+    /// `false` means units of 128 bytes, `true` means units of 1024 bytes.
+    pub scale: bool,
+
+    /// 13-bit buffer size.
+    pub size: u16,
 }
@@ -25,7 +25,14 @@ impl Pmt {
 
 impl WritableLen for Pmt {
     fn writable_len(&self) -> usize {
-        todo!()
+        1 /* pointer_field */ +
+            3 /* table header */ +
+            5 /* table syntax */ +
+            2 /* PCR_PID */ +
+            2 /* program_info_length */ +
+            self.program_info.iter().map(|d| d.writable_len()).sum::<usize>() +
+            self.es_info.iter().map(|es| es.writable_len()).sum::<usize>() +
+            4 /* CRC32 */
     }
 }
 
@@ -41,6 +48,15 @@ pub struct EsInfo {
     pub descriptors: Vec<Descriptor>,
 }
 
+impl WritableLen for EsInfo {
+    fn writable_len(&self) -> usize {
+        1 /* stream_type */ +
+            2 /* elementary_PID */ +
+            2 /* ES_info_length */ +
+            self.descriptors.iter().map(|d| d.writable_len()).sum::<usize>()
+    }
+}
+
 /// Program or elementary stream descriptor.
 #[allow(missing_docs)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -48,3 +64,9 @@ pub struct Descriptor {
     pub tag: u8,
     pub data: Bytes,
 }
+
+impl WritableLen for Descriptor {
+    fn writable_len(&self) -> usize {
+        2 + self.data.len()
+    }
+}
@@ -2,8 +2,20 @@ use bytes::Bytes;
 
 use crate::mpegts::version::VersionNumber;
 
+/// Longest `section_length` of a PSI section (PAT, CAT, PMT and TSDT).
 pub const MAX_SYNTAX_SECTION_LEN: usize = 1021;
 
+/// Longest `section_length` of any other section, e.g. private sections.
+pub const MAX_PRIVATE_SECTION_LEN: usize = 4093;
+
+/// Returns the longest `section_length` allowed for sections with `table_id`.
+pub fn max_section_len(table_id: u8) -> usize {
+    match table_id {
+        0x00..=0x03 => MAX_SYNTAX_SECTION_LEN,
+        _ => MAX_PRIVATE_SECTION_LEN,
+    }
+}
+
 /// Program-specific information.
 #[derive(Debug)]
 pub struct Psi {
@@ -16,6 +28,9 @@ impl Psi {}
 pub struct PsiTable {
     pub header: PsiTableHeader,
     pub syntax: Option<PsiTableSyntax>,
+
+    /// Body of a short-form section, empty when `syntax` is set.
+    pub private_data: Bytes,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -45,3 +60,14 @@ impl PsiTableSyntax {
             4 /* CRC32 */
     }
 }
+
+/// All sections of one version of a long-form PSI table.
+#[derive(Debug, Clone)]
+pub struct VersionedTable {
+    pub header: PsiTableHeader,
+    pub table_id_extension: u16,
+    pub version_number: VersionNumber,
+
+    /// Sections ordered by `section_number`.
+    pub sections: Vec<PsiTableSyntax>,
+}
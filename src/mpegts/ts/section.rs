@@ -10,6 +10,6 @@ pub struct Section {
 
 impl WritableLen for Section {
     fn writable_len(&self) -> usize {
-        todo!()
+        1 /* pointer_field */ + self.data.writable_len()
     }
 }
@@ -17,9 +17,11 @@ impl VersionNumber {
     ///
     /// # Errors
     ///
-    /// If `n` exceeds `VersionNumber::MAX`, it will return an `ErrorKind::InvalidInput` error.
+    /// If `n` exceeds `VersionNumber::MAX`, it will return an `Error::ValueTooLarge` error.
     pub fn from_u8(n: u8) -> Result<Self, Error> {
-        assert!(n <= Self::MAX, "Too large version number: {}", n);
+        if n > Self::MAX {
+            return Err(Error::ValueTooLarge(n.into()));
+        }
 
         Ok(VersionNumber(n))
     }
@@ -1,26 +1,52 @@
-use std::{io::Write, pin::pin};
+use std::{num::NonZeroU64, pin::pin, time::Duration};
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use flowly::{EncodedFrame, Fourcc, Frame, FrameFlags, MemBlock, Service};
 use futures::StreamExt;
-use mpeg2ts::{
-    Error as TsError,
-    es::{StreamId, StreamType},
-    pes::PesHeader,
-    time::{ClockReference, Timestamp},
+
+use crate::Error;
+use crate::mpegts::{
+    bytes::RawData,
+    continuity_counter::ContinuityCounter,
+    io::{Io, Mpeg2tsParser},
+    pid::Pid,
+    stream_id::StreamId,
+    stream_type::StreamType,
+    timestamp::{Clock, PCR, PtsDts, Timestamp, Wrapping},
     ts::{
-        AdaptationField, ContinuityCounter, EsInfo, Pid, ProgramAssociation,
-        TransportScramblingControl, TsHeader, TsPacket, TsPacketWriter, TsPayload, VersionNumber,
-        WriteTsPacket,
-        payload::{self, Pat, Pmt},
+        AdaptationField, AdaptationFieldControl, EsInfo, Null, Pat, Pes, PesHeader, Pmt,
+        ProgramAssociation, TransportScramblingControl, TsHeader, TsPacket, TsPayload,
     },
+    version::VersionNumber,
 };
 
-use crate::Error;
-
 pub struct Mpeg2TsMuxerConfig {
     pub send_aud: bool,
     pub send_params_on_each_keyframe: bool,
+
+    /// Write 192-byte BDAV packets, each prefixed with an arrival time stamp.
+    pub bdav: bool,
+
+    /// Interval between repetitions of the PAT and PMT, which are also sent
+    /// before every keyframe.
+    pub psi_interval: Duration,
+
+    /// Longest time between two PCRs.
+    pub pcr_interval: Duration,
+
+    /// How far the PCR runs behind the DTS of the frames, the time decoders
+    /// get to buffer a frame.
+    pub mux_delay: Duration,
+
+    /// Constant mux rate in bits per second, reached by filling the gaps
+    /// between frames with null packets. PCRs are then stamped with the time
+    /// their packet is sent at this rate. Variable rate if `None`.
+    ///
+    /// There is no T-STD buffer model: a frame is sent once the output
+    /// reaches its DTS minus the mux delay, so the rate and the delay must be
+    /// chosen for the frames to reach the decoder in time without
+    /// overflowing its buffers.
+    pub mux_rate: Option<NonZeroU64>,
 }
 
 impl Default for Mpeg2TsMuxerConfig {
@@ -28,6 +54,11 @@ impl Default for Mpeg2TsMuxerConfig {
         Self {
             send_aud: false,
             send_params_on_each_keyframe: true,
+            bdav: false,
+            psi_interval: Duration::from_millis(100),
+            pcr_interval: Duration::from_millis(40),
+            mux_delay: Duration::ZERO,
+            mux_rate: None,
         }
     }
 }
@@ -38,11 +69,43 @@ const VIDEO_ES_PID: u16 = 257;
 const PES_VIDEO_STREAM_ID: u8 = 224;
 // const PES_AUDIO_STREAM_ID: u8 = 192;
 
+/// Longest gap between frames bridged with PCR-only packets, in 27 MHz units.
+const MAX_PCR_GAP: u64 = 10 * 27_000_000;
+
+/// Bits of the BDAV TP_extra_header holding the arrival time stamp.
+const ARRIVAL_TIME_MASK: u64 = 0x3FFF_FFFF;
+
+/// Arrival time step between packets in 27 MHz units, one packet at 48 Mbit/s.
+const ARRIVAL_TIME_STEP: u64 = 846;
+
+/// Offset in a packet of the byte holding the last bit of the PCR base, the
+/// byte the PCR gives the arrival time of.
+const PCR_BYTE_OFFSET: u64 = 10;
+
 #[derive(Default)]
 pub struct Mpeg2TsMuxer {
     video_continuity_counter: ContinuityCounter,
-    header_sent: bool,
+    pat_continuity_counter: ContinuityCounter,
+    pmt_continuity_counter: ContinuityCounter,
+    stream_type: Option<StreamType>,
+
+    /// DTS in microseconds of the frame the PSI was last sent with.
+    last_psi: Option<u64>,
+
+    /// Last PCR sent, in 27 MHz units without wrapping.
+    last_pcr: Option<u64>,
+
+    /// The next PCR does not follow on from the last one and is sent with
+    /// the `discontinuity_indicator` set.
+    pcr_discontinuity: bool,
+    arrival_time: u64,
+
+    /// Packets written, and the time the first one was sent at in 27 MHz
+    /// units if muxing at a constant rate.
+    packets: u64,
+    mux_start: Option<u64>,
     buf: Vec<u8>,
+    parser: Mpeg2tsParser,
     config: Mpeg2TsMuxerConfig,
 }
 
@@ -50,8 +113,17 @@ impl Mpeg2TsMuxer {
     pub fn new(config: Mpeg2TsMuxerConfig) -> Self {
         Self {
             video_continuity_counter: Default::default(),
-            header_sent: false,
+            pat_continuity_counter: Default::default(),
+            pmt_continuity_counter: Default::default(),
+            stream_type: None,
+            last_psi: None,
+            last_pcr: None,
+            pcr_discontinuity: false,
+            arrival_time: 0,
+            packets: 0,
+            mux_start: None,
             buf: Vec::new(),
+            parser: Mpeg2tsParser::new(),
             config,
         }
     }
@@ -61,22 +133,44 @@ impl Mpeg2TsMuxer {
         frame: F,
         dst: &mut BytesMut,
     ) -> Result<(), Error> {
-        let mut writer = TsPacketWriter::new(dst.writer());
-
-        if !self.header_sent {
-            self.header_sent = true;
-            self.write_header(
-                &mut writer,
-                match frame.codec() {
-                    Fourcc::VIDEO_AVC => StreamType::H264,
-                    Fourcc::VIDEO_HEVC => StreamType::H265,
-                    codec => return Err(Error::MuxUnsupportedCodec(codec)),
-                },
-            )?;
+        let stream_type = match frame.codec() {
+            Fourcc::VIDEO_AVC => StreamType::H264,
+            Fourcc::VIDEO_HEVC => StreamType::H265,
+            codec => return Err(Error::MuxUnsupportedCodec(codec)),
+        };
+
+        // the PMT announces a single video stream
+        if *self.stream_type.get_or_insert(stream_type) != stream_type {
+            return Err(Error::MuxCodecChanged(frame.codec()));
+        }
+
+        let pts = to_timestamp(frame.pts() as u64)?;
+        let dts = to_timestamp(frame.timestamp())?;
+
+        let delay = self.config.mux_delay.as_micros() as u64 * 27;
+        let clock = (frame.timestamp() * 27).saturating_sub(delay);
+
+        let pcr = match self.config.mux_rate {
+            Some(rate) => {
+                self.write_stuffing(dst, rate, clock)?;
+                None
+            }
+
+            None => self.frame_pcr(dst, clock, frame.is_keyframe())?,
+        };
+
+        let now = frame.timestamp();
+        let psi_interval = self.config.psi_interval.as_micros() as u64;
+        let psi_due = self
+            .last_psi
+            .is_none_or(|last| now.abs_diff(last) >= psi_interval);
+
+        if psi_due || frame.is_keyframe() {
+            self.last_psi = Some(now);
+            self.write_psi(dst, stream_type)?;
         }
 
         self.buf.clear();
-        let ts = Timestamp::new((frame.pts() as u64 * 9) / 100).map_err(TsError::from)?;
 
         let send_params = if self.config.send_params_on_each_keyframe {
             frame.is_keyframe()
@@ -102,146 +196,334 @@ impl Mpeg2TsMuxer {
             self.buf.extend_from_slice(chunk.map_to_cpu());
         }
 
-        self.write_packet(&mut writer, ts, frame.is_keyframe())?;
+        self.write_packet(dst, pts, dts, pcr, frame.is_keyframe())?;
 
         Ok(())
     }
 
-    #[inline]
-    fn write_header<W: WriteTsPacket>(
+    /// Bridges the gap since the last PCR and returns the PCR the frame carries, if due.
+    fn frame_pcr(
         &mut self,
-        writer: &mut W,
-        stream_type: StreamType,
-    ) -> Result<(), TsError> {
-        self.write_packets(
-            writer,
-            [
-                &Self::default_pat_packet(),
-                &Self::default_pmt_packet(stream_type),
-            ],
-        )?;
+        dst: &mut BytesMut,
+        clock: u64,
+        is_keyframe: bool,
+    ) -> Result<Option<u64>, Error> {
+        let pcr_interval = self.config.pcr_interval.as_micros() as u64 * 27;
+        if self
+            .last_pcr
+            .is_some_and(|last| clock < last || clock - last > MAX_PCR_GAP)
+        {
+            // the timestamps jumped, a new time base starts right away
+            self.pcr_discontinuity = true;
+        }
+
+        if let Some(mut last) = self.last_pcr
+            && clock > last
+            && clock - last <= MAX_PCR_GAP
+        {
+            // no payload to carry the PCR in between frames
+            while clock - last > pcr_interval {
+                last += pcr_interval;
+                self.write_pcr_packet(dst, last)?;
+            }
+
+            self.last_pcr = Some(last);
+        }
+
+        // packets of a frame never arrive before its PCR
+        self.arrival_time = self.arrival_time.max(clock);
+
+        let pcr_due = is_keyframe
+            || self.pcr_discontinuity
+            || self
+                .last_pcr
+                .is_none_or(|last| clock.abs_diff(last) >= pcr_interval);
+
+        if pcr_due {
+            self.last_pcr = Some(clock);
+            Ok(Some(clock))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Writes null packets, or PCR-only packets when a PCR is due, until the
+    /// output reaches the time a frame is sent at.
+    fn write_stuffing(
+        &mut self,
+        dst: &mut BytesMut,
+        rate: NonZeroU64,
+        clock: u64,
+    ) -> Result<(), Error> {
+        let start = *self.mux_start.get_or_insert(clock);
+        let now = self.output_clock(rate, 0);
+
+        if now.abs_diff(clock) > MAX_PCR_GAP {
+            // restart the output clock rather than stuffing a jump in the
+            // timestamps, with a new time base starting at the next packet
+            self.mux_start = Some(clock.saturating_sub(now - start));
+            self.last_pcr = None;
+            self.pcr_discontinuity = true;
+        }
+
+        while self.output_clock(rate, 0) < clock {
+            match self.next_pcr(rate) {
+                Some(pcr) => self.write_pcr_packet(dst, pcr)?,
+                None => self.write_null_packet(dst)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time in 27 MHz units at which byte `offset` of the next packet is sent
+    /// at the constant mux rate.
+    fn output_clock(&self, rate: NonZeroU64, offset: u64) -> u64 {
+        let bits = (self.packets * TsPacket::SIZE as u64 + offset) as u128 * 8;
+        let ticks = bits * Timestamp::<Clock<PCR>>::RESOLUTION as u128 / rate.get() as u128;
+        self.mux_start.unwrap_or(0) + ticks as u64
+    }
+
+    /// Returns the PCR of the next packet if it is due at the constant mux
+    /// rate. It is sent early enough for the PAT and PMT to still fit in
+    /// before the interval since the last one runs out.
+    fn next_pcr(&mut self, rate: NonZeroU64) -> Option<u64> {
+        let pcr_interval = self.config.pcr_interval.as_micros() as u64 * 27;
+        let pcr = self.output_clock(rate, PCR_BYTE_OFFSET);
+        let next = self.output_clock(rate, 3 * TsPacket::SIZE as u64 + PCR_BYTE_OFFSET);
+
+        if self
+            .last_pcr
+            .is_some_and(|last| next.saturating_sub(last) <= pcr_interval)
+        {
+            return None;
+        }
+
+        self.last_pcr = Some(pcr);
+        Some(pcr)
+    }
+
+    /// Takes the PCR the next packet of a frame carries: the one due with the
+    /// first packet, or any due at the constant mux rate.
+    fn take_pcr(&mut self, pcr: &mut Option<u64>) -> Result<Option<Timestamp<Clock<PCR>>>, Error> {
+        let clock = match self.config.mux_rate {
+            Some(rate) => self.next_pcr(rate),
+            None => pcr.take(),
+        };
+
+        clock.map(to_pcr).transpose()
+    }
+
+    #[inline]
+    fn write_psi(&mut self, dst: &mut BytesMut, stream_type: StreamType) -> Result<(), Error> {
+        let pat = Self::default_pat_packet(self.pat_continuity_counter);
+        let pmt = Self::default_pmt_packet(stream_type, self.pmt_continuity_counter);
+
+        self.write_packets(dst, [&pat, &pmt])?;
+        self.pat_continuity_counter.increment();
+        self.pmt_continuity_counter.increment();
 
         Ok(())
     }
 
     fn write_packet(
         &mut self,
-        writer: &mut TsPacketWriter<impl Write>,
-        ts: Timestamp,
+        dst: &mut BytesMut,
+        pts: Timestamp<PtsDts>,
+        dts: Timestamp<PtsDts>,
+        mut pcr: Option<u64>,
         is_keyframe: bool,
     ) -> Result<(), Error> {
-        let mut header = Self::default_ts_header(VIDEO_ES_PID, self.video_continuity_counter)?;
-        let mut buf = &self.buf[..];
+        let mut header = Self::default_ts_header(VIDEO_ES_PID, self.video_continuity_counter);
+        let data = std::mem::take(&mut self.buf);
+        let mut buf = &data[..];
 
         let packet = {
-            let data = payload::Bytes::new(&buf.chunk()[..buf.remaining().min(150)])?;
-            buf.advance(data.len());
+            let pcr = self.take_pcr(&mut pcr)?;
+            let adaptation_field = self.adaptation_field(is_keyframe, pcr);
+
+            let pes_header = PesHeader {
+                stream_id: StreamId::new(PES_VIDEO_STREAM_ID),
+                priority: false,
+                data_alignment_indicator: false,
+                copyright: false,
+                original_or_copy: false,
+                pts: Some(pts),
+                dts: (dts != pts).then_some(dts),
+                escr: None,
+                es_rate: None,
+                dsm_trick_mode: None,
+                additional_copy_info: None,
+                previous_pes_packet_crc: None,
+                extension: None,
+                stuffing_len: 0,
+                packet_len: 0,
+            };
+
+            let free_len = RawData::MAX_SIZE
+                - adaptation_field.as_ref().map_or(0, |a| a.external_size())
+                - 6
+                - pes_header.optional_header_len() as usize;
+
+            let (chunk, rest) = buf.split_at(buf.len().min(free_len));
+            buf = rest;
 
             TsPacket {
                 header: header.clone(),
-                adaptation_field: is_keyframe.then(|| AdaptationField {
-                    discontinuity_indicator: false,
-                    random_access_indicator: true,
-                    es_priority_indicator: false,
-                    pcr: Some(ClockReference::from(ts)),
-                    opcr: None,
-                    splice_countdown: None,
-                    transport_private_data: Vec::new(),
-                    extension: None,
-                }),
-                payload: Some(TsPayload::Pes(payload::Pes {
-                    header: PesHeader {
-                        stream_id: StreamId::new(PES_VIDEO_STREAM_ID),
-                        priority: false,
-                        data_alignment_indicator: false,
-                        copyright: false,
-                        original_or_copy: false,
-                        pts: Some(ts),
-                        dts: None,
-                        escr: None,
-                    },
-                    pes_packet_len: 0,
-                    data,
+                adaptation_field,
+                payload: Some(TsPayload::Pes(Pes {
+                    header: pes_header,
+                    data: RawData::new(chunk)?,
                 })),
             }
         };
 
-        writer.write_ts_packet(&packet)?;
+        self.serialize_packet(&packet, dst)?;
         header.continuity_counter.increment();
 
-        while buf.has_remaining() {
-            let raw_payload =
-                payload::Bytes::new(&buf.chunk()[..buf.remaining().min(payload::Bytes::MAX_SIZE)])?;
+        while !buf.is_empty() {
+            let pcr = self.take_pcr(&mut pcr)?;
+            let adaptation_field = self.adaptation_field(false, pcr);
+            let free_len =
+                RawData::MAX_SIZE - adaptation_field.as_ref().map_or(0, |a| a.external_size());
 
-            buf.advance(raw_payload.len());
+            let (chunk, rest) = buf.split_at(buf.len().min(free_len));
+            buf = rest;
 
             let packet = TsPacket {
                 header: header.clone(),
-                adaptation_field: None,
-                payload: Some(TsPayload::Raw(raw_payload)),
+                adaptation_field,
+                payload: Some(TsPayload::Raw(RawData::new(chunk)?)),
             };
 
-            writer.write_ts_packet(&packet)?;
+            self.serialize_packet(&packet, dst)?;
             header.continuity_counter.increment();
         }
 
         self.video_continuity_counter = header.continuity_counter;
+        self.buf = data;
         Ok(())
     }
 
+    /// Writes an adaptation-only packet carrying a PCR on the PCR PID.
+    fn write_pcr_packet(&mut self, dst: &mut BytesMut, clock: u64) -> Result<(), Error> {
+        self.arrival_time = self.arrival_time.max(clock);
+
+        // a packet without payload repeats the counter of the last one sent
+        let mut continuity_counter = self.video_continuity_counter;
+        continuity_counter.decrement();
+
+        let packet = TsPacket {
+            header: Self::default_ts_header(VIDEO_ES_PID, continuity_counter),
+            adaptation_field: self.adaptation_field(false, Some(to_pcr(clock)?)),
+            payload: None,
+        };
+
+        self.serialize_packet(&packet, dst)
+    }
+
+    fn write_null_packet(&mut self, dst: &mut BytesMut) -> Result<(), Error> {
+        let packet = TsPacket {
+            header: Self::default_ts_header(Pid::NULL, ContinuityCounter::default()),
+            adaptation_field: None,
+            payload: Some(TsPayload::Null(Null)),
+        };
+
+        self.serialize_packet(&packet, dst)
+    }
+
+    fn adaptation_field(
+        &mut self,
+        random_access: bool,
+        pcr: Option<Timestamp<Clock<PCR>>>,
+    ) -> Option<AdaptationField> {
+        let discontinuity = pcr.is_some() && std::mem::take(&mut self.pcr_discontinuity);
+
+        (random_access || pcr.is_some()).then(|| AdaptationField {
+            discontinuity_indicator: discontinuity,
+            random_access_indicator: random_access,
+            es_priority_indicator: false,
+            pcr,
+            opcr: None,
+            splice_countdown: None,
+            transport_private_data: Vec::new(),
+            extension: None,
+        })
+    }
+
     #[inline]
-    fn write_packets<'a, W: WriteTsPacket, P: IntoIterator<Item = &'a TsPacket>>(
+    fn write_packets<'a, P: IntoIterator<Item = &'a TsPacket>>(
         &mut self,
-        writer: &mut W,
+        dst: &mut BytesMut,
         packets: P,
-    ) -> Result<(), TsError> {
+    ) -> Result<(), Error> {
         packets
             .into_iter()
-            .try_for_each(|pak| writer.write_ts_packet(pak))?;
+            .try_for_each(|pak| self.serialize_packet(pak, dst))?;
 
         Ok(())
     }
 
-    fn default_ts_header(
-        pid: u16,
-        continuity_counter: ContinuityCounter,
-    ) -> Result<TsHeader, TsError> {
-        Ok(TsHeader {
+    fn serialize_packet(&mut self, packet: &TsPacket, dst: &mut BytesMut) -> Result<(), Error> {
+        if self.config.bdav {
+            let arrival_time = match self.config.mux_rate {
+                Some(rate) => self.output_clock(rate, 0),
+                None => {
+                    self.arrival_time += ARRIVAL_TIME_STEP;
+                    self.arrival_time - ARRIVAL_TIME_STEP
+                }
+            };
+
+            // copy_permission_indicator left at 0
+            dst.put_u32((arrival_time & ARRIVAL_TIME_MASK) as u32);
+        }
+
+        self.packets += 1;
+        self.parser.serialize(packet, dst)
+    }
+
+    fn default_ts_header(pid: u16, continuity_counter: ContinuityCounter) -> TsHeader {
+        TsHeader {
             transport_error_indicator: false,
             transport_priority: false,
-            pid: Pid::new(pid)?,
+            pid: Pid(pid),
             transport_scrambling_control: TransportScramblingControl::NotScrambled,
             continuity_counter,
-        })
+            adaptation_field_control: AdaptationFieldControl::PayloadOnly,
+            payload_unit_start_indicator: false,
+        }
     }
 
-    fn default_pat_packet() -> TsPacket {
+    fn default_pat_packet(continuity_counter: ContinuityCounter) -> TsPacket {
         TsPacket {
-            header: Self::default_ts_header(0, Default::default()).unwrap(),
+            header: Self::default_ts_header(Pid::PAT, continuity_counter),
             adaptation_field: None,
             payload: Some(TsPayload::Pat(Pat {
                 transport_stream_id: 1,
                 version_number: VersionNumber::default(),
                 table: vec![ProgramAssociation {
                     program_num: 1,
-                    program_map_pid: Pid::new(PMT_PID).unwrap(),
+                    program_map_pid: Pid(PMT_PID),
                 }],
             })),
         }
     }
 
-    fn default_pmt_packet(stream_type: StreamType) -> TsPacket {
+    fn default_pmt_packet(
+        stream_type: StreamType,
+        continuity_counter: ContinuityCounter,
+    ) -> TsPacket {
         TsPacket {
-            header: Self::default_ts_header(PMT_PID, Default::default()).unwrap(),
+            header: Self::default_ts_header(PMT_PID, continuity_counter),
             adaptation_field: None,
             payload: Some(TsPayload::Pmt(Pmt {
                 program_num: 1,
-                pcr_pid: Some(Pid::new(VIDEO_ES_PID).unwrap()),
+                pcr_pid: Some(Pid(VIDEO_ES_PID)),
                 version_number: VersionNumber::default(),
                 program_info: vec![],
                 es_info: vec![EsInfo {
                     stream_type,
-                    elementary_pid: Pid::new(VIDEO_ES_PID).unwrap(),
+                    elementary_pid: Pid(VIDEO_ES_PID),
                     descriptors: vec![],
                 }],
             })),
@@ -249,6 +531,16 @@ impl Mpeg2TsMuxer {
     }
 }
 
+/// Converts microseconds to a 90 kHz timestamp, wrapping around at 33 bits.
+fn to_timestamp(micros: u64) -> Result<Timestamp<PtsDts>, Error> {
+    Timestamp::<PtsDts>::new((micros * 9 / 100) % PtsDts::MODULUS)
+}
+
+/// Converts 27 MHz ticks to a PCR, wrapping around with its 33-bit base.
+fn to_pcr(clock: u64) -> Result<Timestamp<Clock<PCR>>, Error> {
+    Timestamp::<Clock<PCR>>::new(clock % Clock::<PCR>::MODULUS)
+}
+
 impl<F: EncodedFrame, E: flowly::Error> Service<Result<F, E>> for Mpeg2TsMuxer {
     type Out = Result<Bytes, Error<E>>;
 
@@ -275,3 +567,305 @@ impl<F: EncodedFrame, E: flowly::Error> Service<Result<F, E>> for Mpeg2TsMuxer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::frame::{Mpeg2TsFrame, Mpeg2TsSource};
+
+    fn frame(codec: Fourcc, dts_ms: u64, keyframe: bool, len: usize) -> Mpeg2TsFrame<()> {
+        let source = Arc::new(Mpeg2TsSource::new(codec, 1, VIDEO_ES_PID, ()));
+
+        let mut payload = vec![0, 0, 0, 1, if keyframe { 0x65 } else { 0x41 }];
+        payload.resize(len, 0xAB);
+
+        let dts = dts_ms * 1000;
+        let payload = payload.into();
+        Mpeg2TsFrame::new(
+            dts as i64, dts, keyframe, false, None, false, payload, source,
+        )
+    }
+
+    /// PID, continuity counter and whether the packet has a payload, for every packet.
+    fn headers(out: &[u8]) -> Vec<(u16, u8, bool)> {
+        out.chunks(TsPacket::SIZE)
+            .map(|p| {
+                let pid = u16::from_be_bytes([p[1] & 0x1F, p[2]]);
+                (pid, p[3] & 0x0F, p[3] & 0x10 != 0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pcr_packets_repeat_the_continuity_counter() {
+        let mut muxer = Mpeg2TsMuxer::default();
+        let mut out = BytesMut::new();
+
+        for (dts, keyframe) in [(0, true), (200, false), (240, false), (500, false)] {
+            let frame = frame(Fourcc::VIDEO_AVC, dts, keyframe, 400);
+            muxer.push_frame(frame, &mut out).unwrap();
+        }
+
+        let mut last = None;
+        let mut pcr_packets = 0;
+
+        for (pid, cc, has_payload) in headers(&out) {
+            if pid != VIDEO_ES_PID {
+                continue;
+            }
+
+            if has_payload {
+                assert_eq!(cc, last.map_or(0, |last| (last + 1) & 0x0F));
+                last = Some(cc);
+            } else {
+                assert_eq!(Some(cc), last);
+                pcr_packets += 1;
+            }
+        }
+
+        assert!(pcr_packets > 0);
+    }
+
+    #[test]
+    fn unsupported_codec_writes_nothing() {
+        let mut muxer = Mpeg2TsMuxer::default();
+        let mut out = BytesMut::new();
+
+        let res = muxer.push_frame(frame(Fourcc::AUDIO_AAC, 0, true, 100), &mut out);
+        assert!(matches!(res, Err(Error::MuxUnsupportedCodec(_))));
+        assert!(out.is_empty());
+
+        // no PCR-only packets for the gap since the rejected frame
+        muxer
+            .push_frame(frame(Fourcc::VIDEO_AVC, 200, true, 100), &mut out)
+            .unwrap();
+        assert_eq!(headers(&out)[0].0, Pid::PAT);
+    }
+
+    #[test]
+    fn codec_change_rejected() {
+        let mut muxer = Mpeg2TsMuxer::default();
+        let mut out = BytesMut::new();
+
+        muxer
+            .push_frame(frame(Fourcc::VIDEO_AVC, 0, true, 100), &mut out)
+            .unwrap();
+
+        let len = out.len();
+        let res = muxer.push_frame(frame(Fourcc::VIDEO_HEVC, 40, true, 100), &mut out);
+        assert!(matches!(
+            res,
+            Err(Error::MuxCodecChanged(Fourcc::VIDEO_HEVC))
+        ));
+        assert_eq!(out.len(), len);
+
+        muxer
+            .push_frame(frame(Fourcc::VIDEO_AVC, 80, false, 100), &mut out)
+            .unwrap();
+    }
+
+    #[test]
+    fn pcr_wraps_with_its_base() {
+        let wrap = (1 << 33) * 300;
+
+        assert_eq!(to_pcr(wrap - 1).unwrap().as_u64(), wrap - 1);
+        assert_eq!(to_pcr(wrap).unwrap().as_u64(), 0);
+        assert_eq!(to_pcr(wrap + 299).unwrap().as_u64(), 299);
+    }
+
+    /// PCR of a packet, if it carries one.
+    fn pcr(packet: &[u8]) -> Option<u64> {
+        if packet[3] & 0x20 == 0 || packet[4] == 0 || packet[5] & 0x10 == 0 {
+            return None;
+        }
+
+        let n = u64::from_be_bytes([
+            0, 0, packet[6], packet[7], packet[8], packet[9], packet[10], packet[11],
+        ]);
+        Some((n >> 15) * 300 + (n & 0x1FF))
+    }
+
+    #[test]
+    fn constant_rate_output() {
+        let rate = 4_000_000;
+        let config = Mpeg2TsMuxerConfig {
+            mux_delay: Duration::from_millis(200),
+            mux_rate: NonZeroU64::new(rate),
+            ..Default::default()
+        };
+
+        let mut muxer = Mpeg2TsMuxer::new(config);
+        let mut out = BytesMut::new();
+        let mut starts = Vec::new();
+
+        for i in 0..25 {
+            let keyframe = i % 10 == 0;
+            let len = if keyframe { 6000 } else { 1500 + i * 37 % 500 };
+            let dts = 1000 + 40 * i as u64;
+
+            starts.push((out.len() / TsPacket::SIZE, (dts - 200) * 27_000));
+            muxer
+                .push_frame(frame(Fourcc::VIDEO_AVC, dts, keyframe, len), &mut out)
+                .unwrap();
+        }
+
+        let mux_start = starts[0].1;
+        let packet_time = |index: usize| {
+            let bits = (index * TsPacket::SIZE) as u64 * 8;
+            mux_start + bits * 27_000_000 / rate
+        };
+
+        // PCRs run at the mux rate against the position of their last base bit
+        let mut pcrs = Vec::new();
+        for (index, packet) in out.chunks(TsPacket::SIZE).enumerate() {
+            if let Some(pcr) = pcr(packet) {
+                let bits = (index * TsPacket::SIZE) as u64 * 8 + PCR_BYTE_OFFSET * 8;
+                assert_eq!(pcr, mux_start + bits * 27_000_000 / rate);
+                pcrs.push(pcr);
+            }
+        }
+
+        assert!(pcrs.windows(2).all(|w| w[1] - w[0] <= 40 * 27_000));
+
+        // null or PCR-only packets fill the gaps, so that every frame is sent
+        // when it is due
+        let headers = headers(&out);
+        for &(index, clock) in &starts[1..] {
+            let sent = (index..headers.len())
+                .find(|&i| headers[i].0 != Pid::NULL && headers[i].2)
+                .unwrap();
+
+            assert!(sent > index);
+            assert!(packet_time(sent) >= clock);
+            assert!(packet_time(sent - 1) < clock);
+        }
+
+        assert!(headers.iter().any(|h| h.0 == Pid::NULL));
+    }
+
+    /// PCRs in order, with whether they start a new time base.
+    fn pcrs(out: &[u8]) -> Vec<(u64, bool)> {
+        out.chunks(TsPacket::SIZE)
+            .filter_map(|p| pcr(p).map(|pcr| (pcr, p[5] & 0x80 != 0)))
+            .collect()
+    }
+
+    #[test]
+    fn pcr_discontinuity_flagged_on_timestamp_jump() {
+        for mux_rate in [None, NonZeroU64::new(4_000_000)] {
+            let config = Mpeg2TsMuxerConfig {
+                mux_rate,
+                ..Default::default()
+            };
+
+            let mut muxer = Mpeg2TsMuxer::new(config);
+            let mut out = BytesMut::new();
+
+            for (i, dts) in [20_000, 20_040, 20_080, 1000, 1040, 1080, 40_000, 40_040]
+                .into_iter()
+                .enumerate()
+            {
+                let frame = frame(Fourcc::VIDEO_AVC, dts, i % 3 == 0, 1500);
+                muxer.push_frame(frame, &mut out).unwrap();
+            }
+
+            // the clock only moves forward within a time base
+            let pcrs = pcrs(&out);
+            assert!(!pcrs[0].1);
+            assert!(pcrs.windows(2).all(|w| w[1].1 || w[1].0 > w[0].0));
+            assert_eq!(pcrs.iter().filter(|x| x.1).count(), 2);
+        }
+    }
+
+    /// PTS, DTS and PCR of every PES packet start, in 90 kHz and 27 MHz units.
+    fn pes_timestamps(out: &[u8]) -> Vec<(u64, Option<u64>, Option<u64>)> {
+        let mut parser = Mpeg2tsParser::new();
+
+        out.chunks(TsPacket::SIZE)
+            .filter_map(|packet| {
+                let packet: TsPacket = parser.parse(&mut BytesMut::from(packet)).unwrap();
+                let Some(TsPayload::Pes(pes)) = packet.payload else {
+                    return None;
+                };
+
+                let pcr = packet.adaptation_field.and_then(|x| x.pcr);
+                Some((
+                    pes.header.pts.unwrap().as_u64(),
+                    pes.header.dts.map(|x| x.as_u64()),
+                    pcr.map(|x| x.as_u64()),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dts_written_and_pcr_derived_from_it() {
+        let config = Mpeg2TsMuxerConfig {
+            mux_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let mut muxer = Mpeg2TsMuxer::new(config);
+        let mut out = BytesMut::new();
+
+        // I P B B in decode order, then a frame shown when decoded
+        let frames = [
+            (1000, 1080),
+            (1040, 1200),
+            (1080, 1120),
+            (1120, 1160),
+            (1160, 1160),
+        ];
+        for (i, &(dts, pts)) in frames.iter().enumerate() {
+            let mut frame = frame(Fourcc::VIDEO_AVC, dts, i == 0, 300);
+            frame.pts = pts as i64 * 1000;
+            muxer.push_frame(frame, &mut out).unwrap();
+        }
+
+        let expected: Vec<_> = frames
+            .iter()
+            .map(|&(dts, pts)| {
+                let dts_field = (dts != pts).then_some(dts * 90);
+                (pts * 90, dts_field, Some((dts - 100) * 27_000))
+            })
+            .collect();
+
+        assert_eq!(pes_timestamps(&out), expected);
+    }
+
+    #[test]
+    fn psi_repeated_on_interval_and_keyframes() {
+        let mut muxer = Mpeg2TsMuxer::default();
+        let mut out = BytesMut::new();
+        let mut with_psi = Vec::new();
+
+        for i in 0..13 {
+            let dts = 40 * i;
+            let keyframe = i == 0 || i == 8;
+
+            let start = out.len();
+            let frame = frame(Fourcc::VIDEO_AVC, dts, keyframe, 300);
+            muxer.push_frame(frame, &mut out).unwrap();
+
+            let pids: Vec<_> = headers(&out[start..]).iter().map(|h| h.0).collect();
+            if pids.starts_with(&[Pid::PAT, PMT_PID]) {
+                with_psi.push(dts);
+            }
+        }
+
+        // every 100 ms, restarting at the keyframe at 320 ms
+        assert_eq!(with_psi, [0, 120, 240, 320, 440]);
+
+        for pid in [Pid::PAT, PMT_PID] {
+            let counters: Vec<_> = headers(&out)
+                .into_iter()
+                .filter(|h| h.0 == pid)
+                .map(|h| h.1)
+                .collect();
+
+            assert_eq!(counters, [0, 1, 2, 3, 4]);
+        }
+    }
+}